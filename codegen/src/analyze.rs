@@ -1,54 +1,141 @@
 use proc_macro2::{Span, TokenStream};
 use proc_macro_error::{abort, abort_call_site};
 use quote::quote;
-use syn::{parse_quote, Block, Expr, FnArg, Ident, ReturnType, Type};
+use syn::{
+    parse_quote, Block, Expr, FnArg, GenericArgument, Ident, PathArguments, ReturnType, Type,
+};
 
+use crate::parse::TaskArgs;
 use crate::Ast;
 
 pub(crate) struct Model {
     name: String,
     ident: Ident,
     param_ident: Ident,
+    // the leading `ctx: &TaskContext`, which is not a param
+    ctx_arg: Option<FnArg>,
+    // the `State<T>` arguments, resolved from the shared state
+    state_args: Vec<FnArg>,
     input_args: Vec<FnArg>,
     return_type: Option<Type>,
+    // `T` and `E` when the function returns `Result<T, E>`
+    result_types: Option<(Type, Type)>,
     block: Block,
+    is_async: bool,
+    args: TaskArgs,
     krate: TokenStream,
 }
 
-pub(crate) fn analyze(ast: Ast) -> Model {
+pub(crate) fn analyze(args: TaskArgs, ast: Ast) -> Model {
     let ident = ast.sig.ident.clone();
     let name = ident.to_string();
     let param_ident = get_param_ident(&name);
 
-    let input_args: Vec<FnArg> = ast.sig.inputs.clone().into_iter().collect();
-    let mut return_type = None;
-    if let ReturnType::Type(_, ref ty) = ast.sig.output {
-        return_type = Some((**ty).clone());
+    let mut input_args: Vec<FnArg> = ast.sig.inputs.clone().into_iter().collect();
+    let ctx_arg = match input_args.first() {
+        Some(arg) if is_context(arg) => Some(input_args.remove(0)),
+        _ => None,
     };
+    if let Some(arg) = input_args.iter().find(|arg| is_context(arg)) {
+        abort!(arg, "the task context must be the first parameter");
+    }
+    let (state_args, input_args) = input_args.into_iter().partition(is_state);
+    let return_type = match ast.sig.output {
+        ReturnType::Type(_, ref ty) => Some((**ty).clone()),
+        // a task without return type returns unit, like the function does.
+        ReturnType::Default => Some(parse_quote!(())),
+    };
+    let result_types = return_type.as_ref().and_then(split_result);
     let block = (*ast.block).clone();
+    let is_async = ast.sig.asyncness.is_some();
 
     Model {
         name,
         ident,
         param_ident,
+        ctx_arg,
+        state_args,
         input_args,
         return_type,
+        result_types,
         block,
+        is_async,
+        args,
         krate: quote!(::rust_async_queue),
     }
 }
 
-fn get_param_name(name: &String) -> String {
+/// Split `Result<T, E>` into `T` and `E`.
+fn split_result(ty: &Type) -> Option<(Type, Type)> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Result" {
+        return None;
+    }
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => &args.args,
+        _ => return None,
+    };
+    let types: Vec<&Type> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        })
+        .collect();
+    match types[..] {
+        [ok, err] => Some((ok.clone(), err.clone())),
+        _ => None,
+    }
+}
+
+/// Whether `arg` is `_: &TaskContext`, however the type is spelled out.
+fn is_context(arg: &FnArg) -> bool {
+    let ty = match arg {
+        FnArg::Typed(pt) => &*pt.ty,
+        FnArg::Receiver(_) => return false,
+    };
+    match ty {
+        Type::Reference(r) if r.mutability.is_none() => match &*r.elem {
+            Type::Path(path) if path.qself.is_none() => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|s| s.ident == "TaskContext" && s.arguments.is_none()),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether `arg` is `_: State<T>`, however the type is spelled out.
+fn is_state(arg: &FnArg) -> bool {
+    match arg {
+        FnArg::Typed(pt) => match &*pt.ty {
+            Type::Path(path) if path.qself.is_none() => {
+                path.path.segments.last().is_some_and(|s| {
+                    s.ident == "State" && matches!(s.arguments, PathArguments::AngleBracketed(_))
+                })
+            }
+            _ => false,
+        },
+        FnArg::Receiver(_) => false,
+    }
+}
+
+fn get_param_name(name: &str) -> String {
     format!("{name}Params")
 }
 
-fn get_param_ident(name: &String) -> Ident {
+fn get_param_ident(name: &str) -> Ident {
     let param_name = get_param_name(name);
     syn::Ident::new(&param_name[..], Span::call_site())
 }
 
-fn extract_arg_ident(args: &Vec<FnArg>) -> Vec<Ident> {
-    args.into_iter()
+fn extract_arg_ident(args: &[FnArg]) -> Vec<Ident> {
+    args.iter()
         .map(|fa| match fa {
             FnArg::Typed(pt) => match *pt.pat {
                 syn::Pat::Ident(ref pat) => pat.ident.clone(),
@@ -59,8 +146,8 @@ fn extract_arg_ident(args: &Vec<FnArg>) -> Vec<Ident> {
         .collect()
 }
 
-fn construct_assignments(args: &Vec<FnArg>) -> Vec<Expr> {
-    args.into_iter()
+fn construct_assignments(args: &[FnArg]) -> Vec<Expr> {
+    args.iter()
         .map(|fa| match fa {
             FnArg::Typed(pt) => match *pt.pat {
                 syn::Pat::Ident(ref pat) => {
@@ -96,9 +183,12 @@ impl Model {
     pub fn build_struct(&self) -> TokenStream {
         let ident = &self.ident;
         let param_ident = &self.param_ident;
+        let state_args = &self.state_args;
         quote! {
+            #[allow(non_camel_case_types)]
             struct #ident {
                 params: #param_ident,
+                #(#state_args,)*
             }
         }
     }
@@ -115,6 +205,13 @@ impl Model {
         let return_type = &self.return_type;
         let block = &self.block;
         let assignment = construct_assignments(&self.input_args);
+        let ctx_arg = self.ctx_arg.iter();
+        let state_args = &self.state_args;
+        let asyncness = if self.is_async {
+            quote!(async)
+        } else {
+            quote!()
+        };
 
         quote! {
             impl #ident {
@@ -125,7 +222,7 @@ impl Model {
                         }
                     )
                 }
-                fn _run(params: #param_ident) -> #return_type {
+                #asyncness fn _run(params: #param_ident #(, #ctx_arg)* #(, #state_args)*) -> #return_type {
                     #(#assignment;)*
                     #block
                 }
@@ -139,23 +236,170 @@ impl Model {
         let name = &self.name;
         let ident = &self.ident;
         let param_ident = &self.param_ident;
-        let return_type = &self.return_type;
+        let retry_fns = self.build_retry_fns();
+        let result_fns = self.build_result_fns();
+        let queue_fn = self.build_queue_fn();
+        let time_limit_fns = self.build_time_limit_fns();
+        let state_idents = extract_arg_ident(&self.state_args);
+        let ctx = self.ctx_arg.iter().map(|_| quote!(ctx));
+        // sync bodies go to the blocking pool, async ones stay on the runtime.
+        let call = if self.is_async {
+            let ctx = ctx.map(|_| quote!(_ctx));
+            quote! {
+                #ident::_run(self.params.clone() #(, #ctx)* #(, self.#state_idents.clone())*).await
+            }
+        } else {
+            // the blocking pool needs a context of its own.
+            let ctx_clone = self.ctx_arg.iter().map(|_| quote!(let ctx = _ctx.clone();));
+            quote! {
+                let params = self.params.clone();
+                #(#ctx_clone)*
+                #(let #state_idents = self.#state_idents.clone();)*
+                #krate::app::task::run_blocking(move || #ident::_run(params #(, &#ctx)* #(, #state_idents)*)).await
+            }
+        };
+        let (return_type, run) = match &self.result_types {
+            Some((ok, _)) => (
+                quote!(#ok),
+                quote! {
+                    let res = { #call };
+                    res.map_err(#krate::error::TaskError::failed)
+                },
+            ),
+            None => {
+                let return_type = &self.return_type;
+                (
+                    quote!(#return_type),
+                    quote! {
+                        let res = { #call };
+                        ::std::result::Result::Ok(res)
+                    },
+                )
+            }
+        };
 
         quote! {
+            #[#krate::export::async_trait]
             impl #krate::app::task::AQTask for #ident {
                 const NAME: &'static str = #name;
                 type Params = #param_ident;
                 type Returns = #return_type;
 
-                fn run(&self) -> Self::Returns {
-                    #ident::_run(self.params.clone())
+                async fn run(&self, _ctx: &#krate::app::context::TaskContext) -> #krate::app::task::TaskReturn<Self::Returns> {
+                    #run
                 }
-                fn from_params(params: Self::Params) -> Self {
-                    Self { params }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &#krate::app::state::StateMap,
+                ) -> ::std::result::Result<Self, #krate::error::TracerError> {
+                    ::std::result::Result::Ok(Self {
+                        params #(, #state_idents: _state.get()?)*
+                    })
                 }
+                #retry_fns
+                #result_fns
+                #queue_fn
+                #time_limit_fns
             }
         }
     }
+
+    fn build_result_fns(&self) -> TokenStream {
+        let args = &self.args;
+
+        let mut fns = TokenStream::new();
+        if let Some(ttl) = args.result_ttl {
+            fns.extend(quote! {
+                fn result_ttl() -> ::std::option::Option<::std::time::Duration> {
+                    ::std::option::Option::Some(::std::time::Duration::from_secs_f64(#ttl))
+                }
+            });
+        }
+        if let Some(ignore_result) = args.ignore_result {
+            fns.extend(quote! {
+                fn ignore_result() -> bool {
+                    #ignore_result
+                }
+            });
+        }
+        fns
+    }
+
+    fn build_queue_fn(&self) -> TokenStream {
+        match &self.args.queue {
+            Some(queue) => quote! {
+                fn queue() -> ::std::option::Option<&'static str> {
+                    ::std::option::Option::Some(#queue)
+                }
+            },
+            None => TokenStream::new(),
+        }
+    }
+
+    fn build_time_limit_fns(&self) -> TokenStream {
+        let args = &self.args;
+
+        let mut fns = TokenStream::new();
+        if let Some(limit) = args.time_limit {
+            fns.extend(quote! {
+                fn time_limit() -> ::std::option::Option<::std::time::Duration> {
+                    ::std::option::Option::Some(::std::time::Duration::from_secs_f64(#limit))
+                }
+            });
+        }
+        if let Some(limit) = args.soft_time_limit {
+            fns.extend(quote! {
+                fn soft_time_limit() -> ::std::option::Option<::std::time::Duration> {
+                    ::std::option::Option::Some(::std::time::Duration::from_secs_f64(#limit))
+                }
+            });
+        }
+        fns
+    }
+
+    fn build_retry_fns(&self) -> TokenStream {
+        let krate = &self.krate;
+        let args = &self.args;
+
+        let mut fns = TokenStream::new();
+        if args.has_retry_policy() {
+            let max_retries = args.max_retries.unwrap_or(3);
+            let delay = args.retry_delay.unwrap_or(1.0);
+            let max = args.max_retry_delay.unwrap_or(600.0);
+            let backoff = match args.backoff.as_deref() {
+                Some("exponential") => quote! {
+                    #krate::app::retry::Backoff::Exponential {
+                        base: ::std::time::Duration::from_secs_f64(#delay),
+                        max: ::std::time::Duration::from_secs_f64(#max),
+                    }
+                },
+                Some("jittered") => quote! {
+                    #krate::app::retry::Backoff::Jittered {
+                        base: ::std::time::Duration::from_secs_f64(#delay),
+                        max: ::std::time::Duration::from_secs_f64(#max),
+                    }
+                },
+                _ => quote! {
+                    #krate::app::retry::Backoff::Fixed {
+                        delay: ::std::time::Duration::from_secs_f64(#delay),
+                    }
+                },
+            };
+            fns.extend(quote! {
+                fn retry_policy() -> #krate::app::retry::RetryPolicy {
+                    #krate::app::retry::RetryPolicy::new(#max_retries, #backoff)
+                }
+            });
+        }
+        if let Some(retry_on) = &args.retry_on {
+            fns.extend(quote! {
+                fn retry_on(err: &#krate::error::TaskError) -> bool {
+                    #retry_on(err)
+                }
+            });
+        }
+        fns
+    }
 }
 
 #[cfg(test)]
@@ -169,7 +413,7 @@ mod tests {
         let ast: Ast = parse_quote!(
             fn f(x: bool) -> bool {}
         );
-        let model = analyze(ast);
+        let model = analyze(TaskArgs::default(), ast);
         assert_eq!("f".to_string(), model.name);
         assert_eq!("f".to_string(), model.ident.to_string());
         let input_args = model.input_args.clone();
@@ -198,7 +442,7 @@ mod tests {
                 x + y
             }
         );
-        let model = analyze(ast);
+        let model = analyze(TaskArgs::default(), ast);
         let output = model.build_param_struct();
 
         let expected: ItemStruct = parse_quote!(
@@ -230,10 +474,11 @@ mod tests {
                 x + y
             }
         );
-        let model = analyze(ast);
+        let model = analyze(TaskArgs::default(), ast);
         let output = model.build_struct();
 
         let expected: ItemStruct = parse_quote!(
+            #[allow(non_camel_case_types)]
             struct add {
                 params: addParams,
             }
@@ -255,7 +500,7 @@ mod tests {
                 x + y
             }
         );
-        let model = analyze(ast);
+        let model = analyze(TaskArgs::default(), ast);
         let output = construct_assignments(&model.input_args);
 
         let expected: Vec<Expr> = vec![
@@ -276,7 +521,7 @@ mod tests {
                 x + y
             }
         );
-        let model = analyze(ast);
+        let model = analyze(TaskArgs::default(), ast);
         let output = model.build_struct_impl();
 
         let expected: ItemImpl = parse_quote! {
@@ -301,6 +546,431 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_build_async_task() {
+        let ast = parse_quote!(
+            async fn fetch(url: String) -> usize {
+                url.len()
+            }
+        );
+        let model = analyze(TaskArgs::default(), ast);
+        assert!(model.is_async);
+
+        let output = model.build_struct_impl();
+        let expected: ItemImpl = parse_quote! {
+            impl fetch {
+                fn new(url: String) -> ::rust_async_queue::app::signature::Signature<Self> {
+                    ::rust_async_queue::app::signature::Signature::<Self>::new(fetchParams { url })
+                }
+                async fn _run(params: fetchParams) -> usize {
+                    let url = params.url;
+                    {url.len()}
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+
+        let output = model.build_struct_impl_for_task();
+        let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
+            impl ::rust_async_queue::app::task::AQTask for fetch {
+                const NAME: &'static str = "fetch";
+                type Params = fetchParams;
+                type Returns = usize;
+
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = { fetch::_run(self.params.clone()).await };
+                    ::std::result::Result::Ok(res)
+                }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self { params })
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+    }
+
+    #[test]
+    fn test_build_task_with_context() {
+        let ast = parse_quote!(
+            async fn fetch(ctx: &TaskContext, url: String) -> usize {
+                url.len()
+            }
+        );
+        let model = analyze(TaskArgs::default(), ast);
+        assert!(model.ctx_arg.is_some());
+        assert_eq!(1, model.input_args.len());
+
+        let output = model.build_struct_impl();
+        let expected: ItemImpl = parse_quote! {
+            impl fetch {
+                fn new(url: String) -> ::rust_async_queue::app::signature::Signature<Self> {
+                    ::rust_async_queue::app::signature::Signature::<Self>::new(fetchParams { url })
+                }
+                async fn _run(params: fetchParams, ctx: &TaskContext) -> usize {
+                    let url = params.url;
+                    {url.len()}
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+
+        let output = model.build_struct_impl_for_task();
+        let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
+            impl ::rust_async_queue::app::task::AQTask for fetch {
+                const NAME: &'static str = "fetch";
+                type Params = fetchParams;
+                type Returns = usize;
+
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = { fetch::_run(self.params.clone(), _ctx).await };
+                    ::std::result::Result::Ok(res)
+                }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self { params })
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+
+        // sync bodies get a context of their own
+        let ast = parse_quote!(
+            fn add(ctx: &rust_async_queue::app::context::TaskContext, x: i32) -> i32 {
+                x
+            }
+        );
+        let output = analyze(TaskArgs::default(), ast).build_struct_impl_for_task();
+        let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
+            impl ::rust_async_queue::app::task::AQTask for add {
+                const NAME: &'static str = "add";
+                type Params = addParams;
+                type Returns = i32;
+
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = {
+                        let params = self.params.clone();
+                        let ctx = _ctx.clone();
+                        ::rust_async_queue::app::task::run_blocking(move || add::_run(params, &ctx)).await
+                    };
+                    ::std::result::Result::Ok(res)
+                }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self { params })
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+    }
+
+    #[test]
+    fn test_build_task_with_state() {
+        let ast = parse_quote!(
+            fn count(ctx: &TaskContext, db: State<Db>, table: String) -> usize {
+                db.count(&table)
+            }
+        );
+        let model = analyze(TaskArgs::default(), ast);
+        assert_eq!(1, model.state_args.len());
+        assert_eq!(1, model.input_args.len());
+
+        let output = model.build_struct();
+        let expected: ItemStruct = parse_quote!(
+            #[allow(non_camel_case_types)]
+            struct count {
+                params: countParams,
+                db: State<Db>,
+            }
+        );
+        let actual = parse2::<ItemStruct>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+
+        let output = model.build_struct_impl();
+        let expected: ItemImpl = parse_quote! {
+            impl count {
+                fn new(table: String) -> ::rust_async_queue::app::signature::Signature<Self> {
+                    ::rust_async_queue::app::signature::Signature::<Self>::new(countParams { table })
+                }
+                fn _run(params: countParams, ctx: &TaskContext, db: State<Db>) -> usize {
+                    let table = params.table;
+                    {db.count(&table)}
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+
+        let output = model.build_struct_impl_for_task();
+        let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
+            impl ::rust_async_queue::app::task::AQTask for count {
+                const NAME: &'static str = "count";
+                type Params = countParams;
+                type Returns = usize;
+
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = {
+                        let params = self.params.clone();
+                        let ctx = _ctx.clone();
+                        let db = self.db.clone();
+                        ::rust_async_queue::app::task::run_blocking(move || count::_run(params, &ctx, db)).await
+                    };
+                    ::std::result::Result::Ok(res)
+                }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self {
+                        params,
+                        db: _state.get()?
+                    })
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+
+        // async bodies share the state of the task
+        let ast = parse_quote!(
+            async fn count(db: State<Db>) -> usize {
+                db.len()
+            }
+        );
+        let output = analyze(TaskArgs::default(), ast).build_struct_impl_for_task();
+        let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
+            impl ::rust_async_queue::app::task::AQTask for count {
+                const NAME: &'static str = "count";
+                type Params = countParams;
+                type Returns = usize;
+
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = { count::_run(self.params.clone(), self.db.clone()).await };
+                    ::std::result::Result::Ok(res)
+                }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self {
+                        params,
+                        db: _state.get()?
+                    })
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+    }
+
+    #[test]
+    fn test_build_fallible_task() {
+        let ast = parse_quote!(
+            fn div(x: i32, y: i32) -> Result<i32, String> {
+                x.checked_div(y).ok_or("divide by zero".to_string())
+            }
+        );
+        let model = analyze(TaskArgs::default(), ast);
+        let output = model.build_struct_impl_for_task();
+
+        let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
+            impl ::rust_async_queue::app::task::AQTask for div {
+                const NAME: &'static str = "div";
+                type Params = divParams;
+                type Returns = i32;
+
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = {
+                        let params = self.params.clone();
+                        ::rust_async_queue::app::task::run_blocking(move || div::_run(params)).await
+                    };
+                    res.map_err(::rust_async_queue::error::TaskError::failed)
+                }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self { params })
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+    }
+
+    #[test]
+    fn test_split_result() {
+        let ty: Type = parse_quote!(std::result::Result<Vec<u8>, MyError>);
+        let expected: (Type, Type) = (parse_quote!(Vec<u8>), parse_quote!(MyError));
+        assert_eq!(Some(expected), split_result(&ty));
+
+        let ty: Type = parse_quote!(io::Result<u8>);
+        assert_eq!(None, split_result(&ty));
+        let ty: Type = parse_quote!(i32);
+        assert_eq!(None, split_result(&ty));
+    }
+
+    #[test]
+    fn test_build_result_fns() {
+        let ast = parse_quote!(
+            fn add(x: i32, y: i32) -> i32 {
+                x + y
+            }
+        );
+        let args = TaskArgs {
+            result_ttl: Some(60.0),
+            ignore_result: Some(true),
+            ..Default::default()
+        };
+        let model = analyze(args, ast);
+        let output = model.build_result_fns();
+
+        let expected: ItemImpl = parse_quote! {
+            impl add {
+                fn result_ttl() -> ::std::option::Option<::std::time::Duration> {
+                    ::std::option::Option::Some(::std::time::Duration::from_secs_f64(60f64))
+                }
+                fn ignore_result() -> bool {
+                    true
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(quote!(impl add { #output })).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+    }
+
+    #[test]
+    fn test_build_queue_fn() {
+        let ast = parse_quote!(
+            fn add(x: i32, y: i32) -> i32 {
+                x + y
+            }
+        );
+        let args = TaskArgs {
+            queue: Some("math".into()),
+            ..Default::default()
+        };
+        let model = analyze(args, ast);
+        let output = model.build_queue_fn();
+
+        let expected: ItemImpl = parse_quote! {
+            impl add {
+                fn queue() -> ::std::option::Option<&'static str> {
+                    ::std::option::Option::Some("math")
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(quote!(impl add { #output })).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+    }
+
+    #[test]
+    fn test_build_time_limit_fns() {
+        let ast = parse_quote!(
+            fn add(x: i32, y: i32) -> i32 {
+                x + y
+            }
+        );
+        let args = TaskArgs {
+            time_limit: Some(10.0),
+            soft_time_limit: Some(5.0),
+            ..Default::default()
+        };
+        let model = analyze(args, ast);
+        let output = model.build_time_limit_fns();
+
+        let expected: ItemImpl = parse_quote! {
+            impl add {
+                fn time_limit() -> ::std::option::Option<::std::time::Duration> {
+                    ::std::option::Option::Some(::std::time::Duration::from_secs_f64(10f64))
+                }
+                fn soft_time_limit() -> ::std::option::Option<::std::time::Duration> {
+                    ::std::option::Option::Some(::std::time::Duration::from_secs_f64(5f64))
+                }
+            }
+        };
+        let actual = parse2::<ItemImpl>(quote!(impl add { #output })).unwrap();
+        assert_eq!(expected, actual, "got {}\n", output);
+    }
+
+    #[test]
+    fn test_build_retry_fns() {
+        let ast = parse_quote!(
+            fn add(x: i32, y: i32) -> i32 {
+                x + y
+            }
+        );
+        let args = TaskArgs {
+            max_retries: Some(5),
+            backoff: Some("exponential".into()),
+            retry_delay: Some(2.0),
+            retry_on: Some(parse_quote!(is_transient)),
+            ..Default::default()
+        };
+        let model = analyze(args, ast);
+        let output = model.build_struct_impl_for_task();
+
+        let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
+            impl ::rust_async_queue::app::task::AQTask for add {
+                const NAME: &'static str = "add";
+                type Params = addParams;
+                type Returns = i32;
+
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = {
+                        let params = self.params.clone();
+                        ::rust_async_queue::app::task::run_blocking(move || add::_run(params)).await
+                    };
+                    ::std::result::Result::Ok(res)
+                }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self { params })
+                }
+                fn retry_policy() -> ::rust_async_queue::app::retry::RetryPolicy {
+                    ::rust_async_queue::app::retry::RetryPolicy::new(
+                        5u32,
+                        ::rust_async_queue::app::retry::Backoff::Exponential {
+                            base: ::std::time::Duration::from_secs_f64(2f64),
+                            max: ::std::time::Duration::from_secs_f64(600f64),
+                        }
+                    )
+                }
+                fn retry_on(err: &::rust_async_queue::error::TaskError) -> bool {
+                    is_transient(err)
+                }
+            }
+        };
+        let expected_stream = quote! {#expected};
+
+        let actual = parse2::<ItemImpl>(output.clone()).unwrap();
+        assert_eq!(
+            expected, actual,
+            "want {}\n got {}\n",
+            expected_stream, output
+        )
+    }
+
     #[test]
     fn test_build_struct_impl_for_task() {
         let ast = parse_quote!(
@@ -308,20 +978,28 @@ mod tests {
                 x + y
             }
         );
-        let model = analyze(ast);
+        let model = analyze(TaskArgs::default(), ast);
         let output = model.build_struct_impl_for_task();
 
         let expected: ItemImpl = parse_quote! {
+            #[::rust_async_queue::export::async_trait]
             impl ::rust_async_queue::app::task::AQTask for add {
                 const NAME: &'static str = "add";
                 type Params = addParams;
                 type Returns = i32;
 
-                fn run(&self) -> Self::Returns {
-                    add::_run(self.params.clone())
+                async fn run(&self, _ctx: &::rust_async_queue::app::context::TaskContext) -> ::rust_async_queue::app::task::TaskReturn<Self::Returns> {
+                    let res = {
+                        let params = self.params.clone();
+                        ::rust_async_queue::app::task::run_blocking(move || add::_run(params)).await
+                    };
+                    ::std::result::Result::Ok(res)
                 }
-                fn from_params(params: Self::Params) -> Self {
-                    Self { params }
+                fn from_params(
+                    params: Self::Params,
+                    _state: &::rust_async_queue::app::state::StateMap,
+                ) -> ::std::result::Result<Self, ::rust_async_queue::error::TracerError> {
+                    ::std::result::Result::Ok(Self { params })
                 }
             }
         };
@@ -1,37 +1,155 @@
 use proc_macro2::TokenStream;
-use proc_macro_error::{abort, abort_call_site};
-use syn::{Expr, Item, ItemFn};
+use proc_macro_error::abort;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{Expr, Item, ItemFn, Lit, MetaNameValue, Path, Token};
 
 pub type Ast = ItemFn;
 
-pub(crate) fn parse(args: TokenStream, item: TokenStream) -> Ast {
-    const ERROR: &str = "this attribute takes no arguments";
-    const HELP: &str = "use `#[contracts]`";
-
-    if !args.is_empty() {
-        if let Ok(expr) = syn::parse2::<Expr>(args) {
-            // ../tests/ui/has-expr-argument.rs
-            abort!(expr, ERROR; help = HELP)
-        } else {
-            // ../tests/ui/has-arguments.rs
-            abort_call_site!(ERROR; help = HELP)
-        }
+/// Options given to the attribute, e.g. `#[task(max_retries = 3)]`.
+#[derive(Default)]
+pub(crate) struct TaskArgs {
+    pub max_retries: Option<u32>,
+    pub backoff: Option<String>,
+    /// in seconds
+    pub retry_delay: Option<f64>,
+    /// in seconds
+    pub max_retry_delay: Option<f64>,
+    pub retry_on: Option<Path>,
+    /// in seconds
+    pub result_ttl: Option<f64>,
+    pub ignore_result: Option<bool>,
+    /// default queue of the task
+    pub queue: Option<String>,
+    /// in seconds
+    pub time_limit: Option<f64>,
+    /// in seconds
+    pub soft_time_limit: Option<f64>,
+}
+
+impl TaskArgs {
+    pub fn has_retry_policy(&self) -> bool {
+        self.max_retries.is_some()
+            || self.backoff.is_some()
+            || self.retry_delay.is_some()
+            || self.max_retry_delay.is_some()
     }
+}
+
+pub(crate) fn parse(args: TokenStream, item: TokenStream) -> (TaskArgs, Ast) {
+    let args = parse_args(args);
 
     match syn::parse2::<Item>(item) {
-        Ok(Item::Fn(item)) => item,
+        Ok(Item::Fn(item)) => (args, item),
         Ok(item) => {
-            // ../tests/ui/item-is-not-a-function.rs
             abort!(
                 item,
                 "item is not a function";
-                help = "`#[contracts]` can only be used on functions"
+                help = "`#[task]` can only be used on functions"
             )
         }
         Err(_) => unreachable!(), // ?
     }
 }
 
+fn parse_args(args: TokenStream) -> TaskArgs {
+    const HELP: &str = "use `#[task(name = value, ...)]`";
+
+    let metas = match Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse2(args) {
+        Ok(metas) => metas,
+        Err(e) => abort!(e.span(), e; help = HELP),
+    };
+
+    let mut task_args = TaskArgs::default();
+    for meta in metas {
+        let key = match meta.path.get_ident() {
+            Some(ident) => ident.to_string(),
+            None => abort!(meta.path, "unknown argument"; help = HELP),
+        };
+        match &key[..] {
+            "max_retries" => task_args.max_retries = Some(parse_int(&meta.value)),
+            "backoff" => {
+                let backoff = parse_str(&meta.value);
+                if !["fixed", "exponential", "jittered"].contains(&&backoff[..]) {
+                    abort!(
+                        meta.value,
+                        "unknown backoff";
+                        help = "use one of \"fixed\", \"exponential\" or \"jittered\""
+                    )
+                }
+                task_args.backoff = Some(backoff)
+            }
+            "retry_delay" => task_args.retry_delay = Some(parse_secs(&meta.value)),
+            "max_retry_delay" => task_args.max_retry_delay = Some(parse_secs(&meta.value)),
+            "retry_on" => task_args.retry_on = Some(parse_path(&meta.value)),
+            "result_ttl" => task_args.result_ttl = Some(parse_secs(&meta.value)),
+            "ignore_result" => task_args.ignore_result = Some(parse_bool(&meta.value)),
+            "queue" => task_args.queue = Some(parse_str(&meta.value)),
+            "time_limit" => task_args.time_limit = Some(parse_secs(&meta.value)),
+            "soft_time_limit" => task_args.soft_time_limit = Some(parse_secs(&meta.value)),
+            _ => abort!(meta.path, "unknown argument `{}`", key; help = HELP),
+        }
+    }
+    task_args
+}
+
+fn parse_int(expr: &Expr) -> u32 {
+    match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Int(lit), ..
+        }) => match lit.base10_parse() {
+            Ok(val) => val,
+            Err(e) => abort!(lit, e),
+        },
+        _ => abort!(expr, "expect an integer"),
+    }
+}
+
+fn parse_secs(expr: &Expr) -> f64 {
+    match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Int(lit), ..
+        }) => match lit.base10_parse() {
+            Ok(val) => val,
+            Err(e) => abort!(lit, e),
+        },
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Float(lit),
+            ..
+        }) => match lit.base10_parse() {
+            Ok(val) => val,
+            Err(e) => abort!(lit, e),
+        },
+        _ => abort!(expr, "expect a number of seconds"),
+    }
+}
+
+fn parse_bool(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Bool(lit),
+            ..
+        }) => lit.value,
+        _ => abort!(expr, "expect a boolean"),
+    }
+}
+
+fn parse_str(expr: &Expr) -> String {
+    match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => lit.value(),
+        _ => abort!(expr, "expect a string"),
+    }
+}
+
+fn parse_path(expr: &Expr) -> Path {
+    match expr {
+        Expr::Path(path) => path.path.clone(),
+        _ => abort!(expr, "expect a path to a function"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -39,7 +157,7 @@ mod tests {
 
     #[test]
     fn get_item() {
-        let ast = parse(
+        let (args, ast) = parse(
             quote!(),
             quote!(
                 fn add(x: i32, y: i32) -> i32 {
@@ -47,6 +165,34 @@ mod tests {
                 }
             ),
         );
-        assert_eq!("add", ast.sig.ident.to_string())
+        assert_eq!("add", ast.sig.ident.to_string());
+        assert!(!args.has_retry_policy());
+    }
+
+    #[test]
+    fn get_args() {
+        let args = parse_args(quote!(
+            max_retries = 3,
+            backoff = "exponential",
+            retry_delay = 0.5,
+            max_retry_delay = 60,
+            retry_on = errors::is_transient,
+            result_ttl = 3600,
+            ignore_result = false,
+            queue = "emails",
+            time_limit = 30,
+            soft_time_limit = 25.5,
+        ));
+        assert_eq!(Some(3), args.max_retries);
+        assert_eq!(Some("exponential".to_string()), args.backoff);
+        assert_eq!(Some(0.5), args.retry_delay);
+        assert_eq!(Some(60.0), args.max_retry_delay);
+        let expected: Path = syn::parse_quote!(errors::is_transient);
+        assert_eq!(Some(expected), args.retry_on);
+        assert_eq!(Some(3600.0), args.result_ttl);
+        assert_eq!(Some(false), args.ignore_result);
+        assert_eq!(Some("emails".to_string()), args.queue);
+        assert_eq!(Some(30.0), args.time_limit);
+        assert_eq!(Some(25.5), args.soft_time_limit);
     }
 }
@@ -7,8 +7,8 @@ pub(crate) fn impl_macro(metadata: TokenStream, input: TokenStream) -> TokenStre
     // let input: ItemFn = syn::parse2::<ItemFn>(input).unwrap();
     // println!("{:#?}", input);
 
-    let ast = parse(metadata, input);
-    let model = analyze(ast);
+    let (args, ast) = parse(metadata, input);
+    let model = analyze(args, ast);
     codegen(model)
 }
 
@@ -1,4 +1,3 @@
-use rust_async_queue::error::TaskError;
 use rust_async_queue::{self, app::*};
 use tokio::time::sleep;
 use tokio::time::Duration;
@@ -24,16 +23,21 @@ struct add {
     params: addParam,
 }
 
+#[async_trait]
 impl task::AQTask for add {
     const NAME: &'static str = "add";
     type Params = addParam;
     type Returns = i32;
-    fn run(&self) -> Self::Returns {
-        add::_run(self.params.clone())
+    async fn run(&self, _ctx: &context::TaskContext) -> task::TaskReturn<Self::Returns> {
+        let params = self.params.clone();
+        Ok(task::run_blocking(move || add::_run(params)).await)
     }
 
-    fn from_params(params: Self::Params) -> Self {
-        Self { params }
+    fn from_params(
+        params: Self::Params,
+        _state: &state::StateMap,
+    ) -> Result<Self, error::TracerError> {
+        Ok(Self { params })
     }
 }
 
@@ -52,7 +56,7 @@ impl add {
 async fn async_queue_client(client: Client) -> Result<(), String> {
     let t = add::new(1, 2);
     let result = client.submit(&t).await.map_err(|e| e.to_string())?;
-    let op = client.poll_result(&result, Duration::from_secs(10)).await;
+    let op = result.get(Duration::from_secs(10)).await;
     match op {
         Ok(res) => match res {
             Ok(val) => {
@@ -69,7 +73,11 @@ async fn async_queue_client(client: Client) -> Result<(), String> {
 }
 
 async fn async_queue_server(server: Server) -> Result<(), String> {
-    server.start(2).await.map_err(|e| e.to_string())?;
+    server
+        .handle_signals()
+        .start(2)
+        .await
+        .map_err(|e| e.to_string())?;
     info!("server done");
     Ok(())
 }
@@ -83,7 +91,7 @@ async fn main() {
     let broker_url = "redis://127.0.0.1/";
     let queue = "asyncq:test_queue";
     let name = "async-queue";
-    let aq = AsyncQueue::new(name, queue, broker_url).await;
+    let aq = AsyncQueue::new(name, queue, broker_url).await.unwrap();
     aq.register::<add>().await.unwrap();
 
     let client = aq.client().await.unwrap();
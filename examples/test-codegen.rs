@@ -1,8 +1,73 @@
-use rust_async_queue::task;
+use rust_async_queue::app::context::TaskContext;
+use rust_async_queue::app::state::State;
+use rust_async_queue::app::AsyncQueue;
 
 #[rust_async_queue::task]
 fn add(x: i32, y: i32) -> i32 {
     x + y
 }
 
-fn main() {}
+#[rust_async_queue::task(max_retries = 3, backoff = "exponential", retry_delay = 0.5)]
+fn mul(x: i32, y: i32) -> i32 {
+    x * y
+}
+
+#[rust_async_queue::task]
+async fn sleepy_add(x: i32, y: i32) -> i32 {
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    x + y
+}
+
+#[rust_async_queue::task]
+fn div(x: i32, y: i32) -> Result<i32, String> {
+    x.checked_div(y).ok_or("divide by zero".to_string())
+}
+
+#[rust_async_queue::task(result_ttl = 60, ignore_result = true)]
+fn log(line: String) {
+    println!("{line}");
+}
+
+#[rust_async_queue::task(soft_time_limit = 5)]
+async fn crawl(ctx: &TaskContext, pages: u32) -> u32 {
+    for page in 0..pages {
+        if ctx.is_soft_time_limit_exceeded() {
+            return page;
+        }
+        ctx.report_progress(page).await;
+    }
+    pages
+}
+
+#[rust_async_queue::task]
+fn attempt(ctx: &TaskContext) -> u32 {
+    ctx.retries()
+}
+
+struct Config {
+    greeting: String,
+}
+
+#[rust_async_queue::task]
+fn greet(config: State<Config>, name: String) -> String {
+    format!("{} {name}", config.greeting)
+}
+
+#[rust_async_queue::task]
+async fn greet_later(ctx: &TaskContext, config: State<Config>, name: String) -> String {
+    ctx.report_progress("greeting").await;
+    format!("{} {name}", config.greeting)
+}
+
+#[tokio::main]
+async fn main() {
+    let app = AsyncQueue::new("codegen", "codegen", "memory://")
+        .await
+        .unwrap();
+    app.manage(Config {
+        greeting: "hello".to_string(),
+    })
+    .await;
+    app.register::<greet>().await.unwrap();
+    app.register::<greet_later>().await.unwrap();
+}
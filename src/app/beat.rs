@@ -0,0 +1,332 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use tokio::select;
+use tokio::time::sleep;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use super::shutdown::ShutdownHandle;
+use super::signature::Signature;
+use super::task::AQTask;
+use super::{AsyncQueue, Client};
+use crate::error::{BeatError, ClientError};
+
+/// How often a beat looks for entries that are due.
+const BEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the lock taken to fire an entry is kept,
+/// long enough for every beat to have moved past that run.
+const LOCK_TTL: Duration = Duration::from_secs(3600);
+
+/// When a periodic entry is due.
+pub enum Schedule {
+    /// Every `Duration`, counted from the last run.
+    Interval(Duration),
+    /// At the times matching a cron expression, in UTC.
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    pub fn every(interval: Duration) -> Self {
+        Schedule::Interval(interval)
+    }
+
+    /// Parse a cron expression with a seconds field,
+    /// e.g. `"0 30 9 * * Mon-Fri"` for 9:30 on weekdays.
+    pub fn cron(expr: &str) -> Result<Self, BeatError> {
+        cron::Schedule::from_str(expr)
+            .map(|schedule| Schedule::Cron(Box::new(schedule)))
+            .map_err(|e| BeatError::InvalidSchedule(format!("{}: {}", expr, e)))
+    }
+
+    /// The first time after `last` the entry is due, if any.
+    pub fn next_after(&self, last: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Interval(interval) => Some(last + *interval),
+            Schedule::Cron(schedule) => schedule
+                .after(&DateTime::<Utc>::from(last))
+                .next()
+                .map(SystemTime::from),
+        }
+    }
+
+    /// The latest time after `last` and up to `now` the entry is due, if any.
+    /// Earlier runs missed in between are skipped.
+    pub fn last_due(&self, last: SystemTime, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Interval(interval) => {
+                let elapsed = now.duration_since(last).ok()?;
+                if interval.is_zero() {
+                    return Some(now);
+                }
+                let runs =
+                    u32::try_from(elapsed.as_nanos() / interval.as_nanos()).unwrap_or(u32::MAX);
+                (runs > 0).then(|| last + *interval * runs)
+            }
+            Schedule::Cron(schedule) => schedule
+                .after(&DateTime::<Utc>::from(last))
+                .map(SystemTime::from)
+                .take_while(|due| *due <= now)
+                .last(),
+        }
+    }
+}
+
+type Submit =
+    Box<dyn for<'a> Fn(&'a Client) -> BoxFuture<'a, Result<String, ClientError>> + Send + Sync>;
+
+struct Entry {
+    name: String,
+    schedule: Schedule,
+    submit: Submit,
+}
+
+/// Submits tasks periodically.
+///
+/// The last run of every entry is kept in the broker, so several beats
+/// can run side by side: each run is fired by the one beat taking its lock.
+/// Runs missed while no beat was up are not caught up, the entry fires once.
+pub struct Beat {
+    app: Arc<AsyncQueue>,
+    client: Client,
+    owner: String,
+    entries: Vec<Entry>,
+    shutdown: ShutdownHandle,
+    signals: bool,
+}
+
+impl Beat {
+    pub(crate) fn new(app: Arc<AsyncQueue>, client: Client) -> Self {
+        Beat {
+            owner: format!("{}:beat:{}", app.name, Uuid::new_v4()),
+            app,
+            client,
+            entries: Vec::new(),
+            shutdown: ShutdownHandle::default(),
+            signals: false,
+        }
+    }
+
+    /// Submit task `T` with `params` according to `schedule`.
+    /// `name` identifies the entry across beats and restarts.
+    pub fn add<T: AQTask + 'static>(
+        &mut self,
+        name: impl ToString,
+        schedule: Schedule,
+        params: T::Params,
+    ) -> Result<(), BeatError> {
+        let name = name.to_string();
+        if self.entries.iter().any(|e| e.name == name) {
+            return Err(BeatError::DuplicateEntry(name));
+        }
+        let submit: Submit = Box::new(move |client| {
+            let sig = Signature::<T>::new(params.clone());
+            Box::pin(async move { client.submit(&sig).await.map(|r| r.get_id()) })
+        });
+        self.entries.push(Entry {
+            name,
+            schedule,
+            submit,
+        });
+        Ok(())
+    }
+
+    /// Stop the beat on SIGINT and SIGTERM.
+    pub fn handle_signals(mut self) -> Self {
+        self.signals = true;
+        self
+    }
+
+    /// A handle to stop the beat once started.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Fire the entries when they are due, until a shutdown is asked for.
+    pub async fn start(&self) -> Result<(), BeatError> {
+        let signals = match self.signals {
+            true => Some(self.shutdown.listen_signals()?),
+            false => None,
+        };
+        let names: Vec<_> = self.entries.iter().map(|e| &e.name[..]).collect();
+        info!(owner = self.owner, "beat start with {:?}", names);
+        loop {
+            select! {
+                _ = sleep(BEAT_INTERVAL) => {
+                    self.tick(SystemTime::now()).await;
+                },
+                _ = self.shutdown.warm() => break,
+            }
+        }
+        if let Some(signals) = signals {
+            signals.abort();
+        }
+        info!("beat closed");
+        Ok(())
+    }
+
+    /// Fire every entry due at `now`, returns how many were fired.
+    async fn tick(&self, now: SystemTime) -> usize {
+        let mut fired = 0;
+        for entry in &self.entries {
+            match self.fire_if_due(entry, now).await {
+                Ok(true) => fired += 1,
+                Ok(false) => {}
+                Err(e) => error!("fail to fire periodic entry {}, {}", entry.name, e),
+            }
+        }
+        fired
+    }
+
+    async fn fire_if_due(&self, entry: &Entry, now: SystemTime) -> Result<bool, BeatError> {
+        let broker = self.client.broker.as_ref();
+        let key = format!("{}:beat:{}", self.app.name, entry.name);
+        // the first beat to see an entry starts its schedule for everyone
+        broker.set_nx(&key, &millis(now).to_string(), None).await?;
+        let last = match broker.get(&key).await? {
+            Some(val) => from_millis(&val).unwrap_or(now),
+            None => now,
+        };
+        let due = match entry.schedule.last_due(last, now) {
+            Some(due) => due,
+            None => return Ok(false),
+        };
+
+        let lock = format!("{}:{}", key, millis(due));
+        if !broker.set_nx(&lock, &self.owner, Some(LOCK_TTL)).await? {
+            return Ok(false);
+        }
+        let id = match (entry.submit)(&self.client).await {
+            Ok(id) => id,
+            Err(e) => {
+                // let the next tick, of any beat, try this run again.
+                broker.delete(&lock).await?;
+                return Err(e.into());
+            }
+        };
+        // the schedule goes on from the run, not from when it was fired,
+        // so a late tick does not make it drift.
+        broker.set(&key, &millis(due).to_string(), None).await?;
+        info!("periodic entry {} submitted as {}", entry.name, id);
+        Ok(true)
+    }
+}
+
+fn millis(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn from_millis(val: &str) -> Option<SystemTime> {
+    let millis = val.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::tests::{Add, AddParams};
+    use crate::error::BrokerError;
+
+    #[test]
+    fn test_schedule() {
+        let last = UNIX_EPOCH + Duration::from_secs(90);
+        let every = Schedule::every(Duration::from_secs(60));
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(150)),
+            every.next_after(last)
+        );
+        let hourly = Schedule::cron("0 0 * * * *").unwrap();
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(3600)),
+            hourly.next_after(last)
+        );
+        let now = UNIX_EPOCH + Duration::from_secs(280);
+        assert_eq!(None, every.last_due(last, last + Duration::from_secs(59)));
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(270)),
+            every.last_due(last, now)
+        );
+        let now = UNIX_EPOCH + Duration::from_secs(3 * 3600 + 5);
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(3 * 3600)),
+            hourly.last_due(last, now)
+        );
+        assert!(matches!(
+            Schedule::cron("every hour"),
+            Err(BeatError::InvalidSchedule(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_single_fire() {
+        let aq = AsyncQueue::new("test", "beat_queue", "memory://")
+            .await
+            .unwrap();
+        let mut beats = Vec::new();
+        for _ in 0..2 {
+            let mut beat = aq.beat().await.unwrap();
+            let every = Schedule::every(Duration::from_secs(60));
+            beat.add::<Add>("add", every, AddParams { x: 1, y: 2 })
+                .unwrap();
+            beats.push(beat);
+        }
+        let now = SystemTime::now();
+        assert_eq!(0, beats[0].tick(now).await);
+        assert_eq!(0, beats[1].tick(now).await);
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(1, beats[0].tick(later).await);
+        assert_eq!(0, beats[1].tick(later).await);
+        let later = later + Duration::from_secs(1);
+        assert_eq!(0, beats[1].tick(later).await);
+        // the schedule does not drift with late ticks
+        let later = now + Duration::from_secs(120);
+        assert_eq!(1, beats[1].tick(later).await);
+        // missed runs are not caught up
+        let later = now + Duration::from_secs(600);
+        assert_eq!(1, beats[0].tick(later).await);
+        assert_eq!(0, beats[1].tick(later + Duration::from_secs(1)).await);
+
+        let broker = beats[0].client.broker.clone();
+        assert!(broker.dequeue("beat_queue").await.unwrap().is_some());
+
+        let mut beat = aq.beat().await.unwrap();
+        let every = Schedule::every(Duration::from_secs(60));
+        beat.add::<Add>("add", every, AddParams { x: 1, y: 2 })
+            .unwrap();
+        let every = Schedule::every(Duration::from_secs(60));
+        assert!(matches!(
+            beat.add::<Add>("add", every, AddParams { x: 1, y: 2 }),
+            Err(BeatError::DuplicateEntry(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_fire() {
+        let aq = AsyncQueue::new("test", "beat_queue", "memory://")
+            .await
+            .unwrap();
+        let mut beat = aq.beat().await.unwrap();
+        beat.entries.push(Entry {
+            name: "fail".to_string(),
+            schedule: Schedule::every(Duration::from_secs(60)),
+            submit: Box::new(|_| {
+                Box::pin(async { Err(BrokerError::Unsupported("submit".into()).into()) })
+            }),
+        });
+        let now = SystemTime::now();
+        assert_eq!(0, beat.tick(now).await);
+
+        // the run is not locked away by the failed attempt
+        let later = now + Duration::from_secs(61);
+        let entry = &beat.entries[0];
+        assert!(beat.fire_if_due(entry, later).await.is_err());
+        assert!(beat.fire_if_due(entry, later).await.is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::message::Message;
+use super::record::{Retention, TaskRecord};
+use crate::broker::Broker;
+use crate::error::WorkerError;
+
+/// What a running task knows about itself, handed to `AQTask::run`.
+#[derive(Clone, Debug)]
+pub struct TaskContext {
+    id: String,
+    retries: u32,
+    queue: String,
+    deadline: Option<SystemTime>,
+    cancel: CancellationToken,
+    soft_limit: CancellationToken,
+    progress: Option<Progress>,
+}
+
+impl TaskContext {
+    pub(crate) fn new(msg: &Message, queue: String, deadline: Option<SystemTime>) -> Self {
+        TaskContext {
+            id: msg.get_id(),
+            retries: msg.get_retries(),
+            queue,
+            deadline,
+            cancel: CancellationToken::new(),
+            soft_limit: CancellationToken::new(),
+            progress: None,
+        }
+    }
+
+    /// Store the progress reported by the task in `progress`.
+    pub(crate) fn report_to(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The id of the task, the one of its `AsyncResult`.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// How many times the task was retried before this attempt.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// The queue the task was submitted to, whatever its priority.
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+
+    /// When the hard time limit aborts the task, if it has one.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    /// Whether the task was revoked with `terminate` and should stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Resolves once the task is revoked with `terminate`,
+    /// for long-running tasks to `select!` on.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancel.cancel()
+    }
+
+    /// Whether the task ran past its soft time limit and should wrap up
+    /// before the hard one aborts it.
+    pub fn is_soft_time_limit_exceeded(&self) -> bool {
+        self.soft_limit.is_cancelled()
+    }
+
+    /// Resolves once the task ran past its soft time limit.
+    pub async fn soft_time_limit_exceeded(&self) {
+        self.soft_limit.cancelled().await
+    }
+
+    pub(crate) fn exceed_soft_time_limit(&self) {
+        self.soft_limit.cancel()
+    }
+
+    /// Let clients know how far the task got, through `AsyncResult::progress`.
+    ///
+    /// Progress is best effort: it is only logged if it cannot be stored,
+    /// and dropped once the task finished.
+    pub async fn report_progress<P: Serialize>(&self, progress: P) {
+        let Some(reporter) = &self.progress else {
+            return;
+        };
+        if let Err(e) = reporter.report(progress).await {
+            warn!("fail to report progress of task {}, {}", self.id, e);
+        }
+    }
+}
+
+/// Where a running task writes its progress: the record of its current attempt.
+#[derive(Clone)]
+pub(crate) struct Progress {
+    id: String,
+    broker: Arc<dyn Broker>,
+    retention: Retention,
+    // taken back by the worker once the task finished
+    record: Arc<Mutex<Option<TaskRecord>>>,
+}
+
+impl Progress {
+    pub fn new(
+        id: String,
+        broker: Arc<dyn Broker>,
+        retention: Retention,
+        record: TaskRecord,
+    ) -> Self {
+        Progress {
+            id,
+            broker,
+            retention,
+            record: Arc::new(Mutex::new(Some(record))),
+        }
+    }
+
+    async fn report<P: Serialize>(&self, progress: P) -> Result<(), WorkerError> {
+        let mut record = self.record.lock().await;
+        let Some(record) = record.as_mut() else {
+            return Ok(());
+        };
+        record.report(serde_json::to_value(progress)?);
+        record
+            .save::<WorkerError>(self.broker.as_ref(), &self.id, self.retention)
+            .await
+    }
+
+    /// The record with the last progress, no more progress is stored from now on.
+    pub async fn finish(&self) -> Option<TaskRecord> {
+        self.record.lock().await.take()
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress").field("id", &self.id).finish()
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use super::message::Message;
+use crate::broker::Broker;
+use crate::error::{ClientError, MsgError};
+
+/// A message no worker could handle, kept aside for a human to look at.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// the message as it was delivered
+    pub body: String,
+    /// the broker queue it was taken from, where `requeue` sends it back
+    pub queue: String,
+    /// why it could not be handled
+    pub reason: String,
+    pub failed_at: SystemTime,
+}
+
+impl DeadLetter {
+    pub fn new(body: String, queue: String, reason: String) -> Self {
+        DeadLetter {
+            body,
+            queue,
+            reason,
+            failed_at: SystemTime::now(),
+        }
+    }
+
+    pub fn serialize(&self) -> Result<String, MsgError> {
+        serde_json::to_string(self).map_err(|e| e.into())
+    }
+
+    pub fn deserialize(val: &str) -> Result<Self, MsgError> {
+        serde_json::from_str(val).map_err(|e| e.into())
+    }
+
+    /// The dead message, unless it is the reason it died.
+    pub fn message(&self) -> Option<Message> {
+        serde_json::from_str(&self.body).ok()
+    }
+}
+
+/// Lists, inspects, requeues and purges the dead letters of an `AsyncQueue`.
+pub struct DeadLetters {
+    queue: String,
+    broker: Box<dyn Broker>,
+}
+
+impl DeadLetters {
+    pub(crate) fn new(queue: String, broker: Box<dyn Broker>) -> Self {
+        DeadLetters { queue, broker }
+    }
+
+    /// Up to `count` dead letters from `start` on, oldest first.
+    pub async fn list(&self, start: usize, count: usize) -> Result<Vec<DeadLetter>, ClientError> {
+        let vals = self.broker.peek(&self.queue, start, count).await?;
+        let letters = vals
+            .iter()
+            .map(|val| DeadLetter::deserialize(val))
+            .collect::<Result<_, _>>()?;
+        Ok(letters)
+    }
+
+    /// The dead letter of the task `id`, if there is one.
+    pub async fn inspect(&self, id: &str) -> Result<Option<DeadLetter>, ClientError> {
+        const PAGE: usize = 100;
+        let mut start = 0;
+        loop {
+            let letters = self.list(start, PAGE).await?;
+            let found = letters
+                .iter()
+                .find(|letter| letter.message().is_some_and(|msg| msg.get_id() == id));
+            if let Some(letter) = found {
+                return Ok(Some(letter.clone()));
+            }
+            if letters.len() < PAGE {
+                return Ok(None);
+            }
+            start += PAGE;
+        }
+    }
+
+    /// Send `letter` back to the queue it died in, for another try.
+    /// Returns whether it was still a dead letter.
+    pub async fn requeue(&self, letter: &DeadLetter) -> Result<bool, ClientError> {
+        let val = letter.serialize()?;
+        if self.broker.remove(&self.queue, &val).await? == 0 {
+            return Ok(false);
+        }
+        self.broker.enqueue(&letter.queue, &letter.body).await?;
+        Ok(true)
+    }
+
+    /// Drop every dead letter, returns how many there were.
+    pub async fn purge(&self) -> Result<usize, ClientError> {
+        Ok(self.broker.purge(&self.queue).await?)
+    }
+}
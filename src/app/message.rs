@@ -1,15 +1,32 @@
 use crate::error::MsgError;
 
-use super::{AQTask, Signature};
+use super::{retry::RetryPolicy, AQTask, Signature};
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Message {
     id: String,
     name: String,
     payload: Vec<u8>,
+    #[serde(default)]
+    retries: u32,
+    #[serde(default)]
+    retry_policy: Option<RetryPolicy>,
+    #[serde(default)]
+    ignore_result: bool,
+    #[serde(default)]
+    expires: Option<SystemTime>,
+    #[serde(default)]
+    priority: u8,
+    #[serde(default)]
+    time_limit: Option<Duration>,
+    #[serde(default)]
+    soft_time_limit: Option<Duration>,
+    #[serde(default)]
+    queue: Option<String>,
 }
 
 impl Message {
@@ -18,17 +35,37 @@ impl Message {
     }
 
     pub fn serialize(&self) -> Result<String, MsgError> {
-        return serde_json::to_string(self).map_err(|e| e.into());
+        serde_json::to_string(self).map_err(|e| e.into())
     }
 
     pub fn new_with_id(id: String, name: String, payload: Vec<u8>) -> Message {
         Message {
-            id: id,
+            id,
             name,
             payload,
+            retries: 0,
+            retry_policy: None,
+            ignore_result: false,
+            expires: None,
+            priority: 0,
+            time_limit: None,
+            soft_time_limit: None,
+            queue: None,
         }
     }
 
+    /// Record the queue the task was submitted to, whatever its priority.
+    pub fn with_queue(mut self, queue: String) -> Message {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// The message for the next attempt of this task.
+    pub fn retry(mut self) -> Message {
+        self.retries += 1;
+        self
+    }
+
     pub fn get_id(&self) -> String {
         self.id.clone()
     }
@@ -40,6 +77,39 @@ impl Message {
     pub fn get_payload(&self) -> &Vec<u8> {
         &self.payload
     }
+
+    pub fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub fn get_retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry_policy.clone()
+    }
+
+    pub fn get_ignore_result(&self) -> bool {
+        self.ignore_result
+    }
+
+    pub fn get_priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn get_time_limit(&self) -> Option<Duration> {
+        self.time_limit
+    }
+
+    pub fn get_soft_time_limit(&self) -> Option<Duration> {
+        self.soft_time_limit
+    }
+
+    pub fn get_queue(&self) -> Option<String> {
+        self.queue.clone()
+    }
+
+    /// Whether the task must no longer be started.
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|at| at <= SystemTime::now())
+    }
 }
 
 impl<T> TryFrom<&Signature<T>> for Message
@@ -52,6 +122,13 @@ where
         let name = value.name().to_string();
         let params = value.get_params();
         let payload = serde_json::to_vec(&params)?;
-        Ok(Message::new_with_id(id, name, payload))
+        let mut msg = Message::new_with_id(id, name, payload);
+        msg.retry_policy = value.get_retry_policy();
+        msg.ignore_result = value.get_ignore_result();
+        msg.expires = value.get_expires();
+        msg.priority = value.get_priority();
+        msg.time_limit = value.get_time_limit();
+        msg.soft_time_limit = value.get_soft_time_limit();
+        Ok(msg)
     }
 }
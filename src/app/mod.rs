@@ -1,39 +1,84 @@
+pub mod beat;
+pub mod context;
+pub mod dead_letter;
 pub mod message;
+pub mod policy;
+pub mod record;
+pub mod retry;
+mod revoke;
+pub mod shutdown;
 mod signal;
 pub mod signature;
+pub mod state;
 pub mod task;
 pub mod tracer;
 mod worker;
 
+use self::beat::Beat;
+use self::dead_letter::DeadLetters;
 use self::message::Message;
+use self::policy::{ConsumePolicy, Selector};
+use self::record::{Retention, TaskRecord, TaskState};
+use self::revoke::Revocation;
+use self::shutdown::ShutdownHandle;
 use self::signature::Signature;
+use self::state::StateMap;
 use self::tracer::TracerTrait;
-use tokio::time::timeout;
-use tracing::debug;
-use worker::Worker;
+use worker::{Delivery, InFlight, Worker};
 
 use crate::async_result::AsyncResult;
+use crate::broker;
 use crate::broker::Broker;
 use crate::broker::BrokerBuilder;
-use crate::broker::RedisBrokerBuilder;
-use crate::error::{ClientError, QueueError, ServerError, TracerError};
+use crate::error::{BrokerError, ClientError, QueueError, ServerError, TracerError};
 
-use signal::*;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::SystemTime;
 use task::*;
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// How long task results are kept unless configured otherwise.
+const DEFAULT_RESULT_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// How often a server gives expired reservations back to the queue.
+const REAPER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often a server moves delayed tasks whose time came onto the queue.
+const PROMOTE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The highest priority a task can have, see `Signature::priority`.
+pub const MAX_PRIORITY: u8 = 9;
+
+/// How long a server waits on its queues at once.
+const QUEUE_WAIT: Duration = Duration::from_secs(1);
+
+/// The broker queue holding the tasks of `queue` with `priority`.
+/// Tasks with the default priority stay in `queue` itself.
+fn priority_queue(queue: &str, priority: u8) -> String {
+    match priority.min(MAX_PRIORITY) {
+        0 => queue.to_string(),
+        priority => format!("{}:p{}", queue, priority),
+    }
+}
 
 pub struct AsyncQueue {
     name: String,
     queue: String,
     broker_builder: Arc<dyn BrokerBuilder>,
     timeout: u32,
+    result_ttl: RwLock<Option<Duration>>,
+    dead_letter_queue: RwLock<Option<String>>,
+    /// task name patterns and the queue they are routed to, in order
+    routes: RwLock<Vec<(String, String)>>,
     task_builders: RwLock<HashMap<String, tracer::TraceBuilder>>,
+    state: RwLock<StateMap>,
 }
 
 impl AsyncQueue {
@@ -41,24 +86,35 @@ impl AsyncQueue {
         name: impl ToString,
         queue: impl ToString,
         broker_url: impl ToString,
-    ) -> Arc<AsyncQueue> {
-        let rbb = RedisBrokerBuilder::new(broker_url.to_string());
+    ) -> Result<Arc<AsyncQueue>, QueueError> {
+        let broker_builder = broker::builder_from_url(broker_url.to_string())?;
+        Ok(AsyncQueue::with_broker(name, queue, broker_builder))
+    }
 
-        // let broker = rbb.build(10).await.unwrap();
+    /// Create an `AsyncQueue` on top of a custom `BrokerBuilder`.
+    pub fn with_broker(
+        name: impl ToString,
+        queue: impl ToString,
+        broker_builder: Arc<dyn BrokerBuilder>,
+    ) -> Arc<AsyncQueue> {
         Arc::new(AsyncQueue {
             name: name.to_string(),
             queue: queue.to_string(),
-            broker_builder: Arc::from(rbb),
+            broker_builder,
             timeout: 10,
+            result_ttl: RwLock::new(Some(DEFAULT_RESULT_TTL)),
+            dead_letter_queue: RwLock::new(Some(format!("{}:dead", queue.to_string()))),
+            routes: RwLock::new(Vec::new()),
             task_builders: RwLock::new(HashMap::new()),
+            state: RwLock::new(StateMap::default()),
         })
     }
 
     pub async fn client(self: &Arc<Self>) -> Result<Client, QueueError> {
         let broker = self.broker_builder.build(self.timeout).await?;
         Ok(Client {
-            queue: self.queue.clone(),
-            broker: broker,
+            app: self.clone(),
+            broker: Arc::from(broker),
         })
     }
 
@@ -66,17 +122,95 @@ impl AsyncQueue {
         let broker = self.broker_builder.build(self.timeout).await?;
         Ok(Server {
             app: self.clone(),
-            queue: self.queue.clone(),
-            broker: broker,
+            queues: vec![self.queue.clone()],
+            policy: ConsumePolicy::default(),
+            consumer: format!("{}:{}", self.name, Uuid::new_v4()),
+            visibility_timeout: Duration::from_secs(3600),
+            grace_period: Duration::from_secs(30),
+            shutdown: ShutdownHandle::default(),
+            signals: false,
+            broker,
             timeout: self.timeout,
             broker_builder: self.broker_builder.clone(),
         })
     }
 
+    /// Create a `Beat` submitting periodic tasks through this queue.
+    pub async fn beat(self: &Arc<Self>) -> Result<Beat, QueueError> {
+        Ok(Beat::new(self.clone(), self.client().await?))
+    }
+
     pub fn get_info(&self) -> String {
         self.name.clone()
     }
 
+    /// Set how long task results are kept in the broker, `None` keeps them forever.
+    /// Tasks can override it with `#[task(result_ttl = ...)]`. Defaults to one day.
+    pub async fn set_result_ttl(&self, ttl: Option<Duration>) {
+        *self.result_ttl.write().await = ttl;
+    }
+
+    /// The result ttl of a task, given the one the task declares.
+    pub(crate) async fn result_ttl(&self, task_ttl: Option<Duration>) -> Option<Duration> {
+        match task_ttl {
+            Some(ttl) => Some(ttl),
+            None => *self.result_ttl.read().await,
+        }
+    }
+
+    /// Set where messages no worker can handle are kept, `None` drops them.
+    /// Defaults to `<queue>:dead`.
+    pub async fn set_dead_letter_queue(&self, queue: Option<String>) {
+        *self.dead_letter_queue.write().await = queue;
+    }
+
+    pub(crate) async fn dead_letter_queue(&self) -> Option<String> {
+        self.dead_letter_queue.read().await.clone()
+    }
+
+    /// Manage the messages kept in the dead-letter queue.
+    pub async fn dead_letters(&self) -> Result<DeadLetters, QueueError> {
+        let queue = self
+            .dead_letter_queue()
+            .await
+            .ok_or(QueueError::DeadLetterDisabled)?;
+        let broker = self.broker_builder.build(self.timeout).await?;
+        Ok(DeadLetters::new(queue, broker))
+    }
+
+    /// Send the tasks whose name matches `pattern` to `queue`,
+    /// unless the signature names a queue. `pattern` is either a task name
+    /// or a prefix followed by `*`, the first matching rule wins.
+    pub async fn route(&self, pattern: impl ToString, queue: impl ToString) {
+        let mut routes = self.routes.write().await;
+        routes.push((pattern.to_string(), queue.to_string()));
+    }
+
+    /// The queue a task is sent to, given the ones named by its signature
+    /// and by the task itself.
+    pub(crate) async fn queue_for(
+        &self,
+        name: &str,
+        sig_queue: Option<String>,
+        task_queue: Option<&str>,
+    ) -> String {
+        if let Some(queue) = sig_queue {
+            return queue;
+        }
+        let routes = self.routes.read().await;
+        let route = routes
+            .iter()
+            .find(|(pattern, _)| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            });
+        match (route, task_queue) {
+            (Some((_, queue)), _) => queue.clone(),
+            (None, Some(queue)) => queue.to_string(),
+            (None, None) => self.queue.clone(),
+        }
+    }
+
     pub async fn register<T: AQTask + 'static>(&self) -> Result<(), QueueError> {
         let name = T::NAME;
         let mut task_builders = self.task_builders.write().await;
@@ -88,6 +222,12 @@ impl AsyncQueue {
         Ok(())
     }
 
+    /// Share `value` with every task taking a `State<T>` parameter,
+    /// replacing the value of the same type.
+    pub async fn manage<T: Send + Sync + 'static>(&self, value: T) {
+        self.state.write().await.insert(value);
+    }
+
     pub(crate) async fn get_tracer(
         self: &Arc<Self>,
         name: String,
@@ -95,7 +235,8 @@ impl AsyncQueue {
     ) -> Result<Box<dyn TracerTrait>, TracerError> {
         let task_builders = self.task_builders.read().await;
         if let Some(builder) = task_builders.get(&name) {
-            let t = builder(msg)?;
+            let state = self.state.read().await;
+            let t = builder(msg, &state)?;
             Ok(t)
         } else {
             Err(TracerError::TaskNotFound(name))
@@ -104,132 +245,1095 @@ impl AsyncQueue {
 }
 
 pub struct Client {
-    queue: String,
-    broker: Box<dyn Broker>,
+    app: Arc<AsyncQueue>,
+    broker: Arc<dyn Broker>,
 }
 
 impl Client {
     pub async fn submit<T: AQTask>(&self, s: &Signature<T>) -> Result<AsyncResult<T>, ClientError> {
-        let msg = Message::try_from(s)?;
+        let queue = self.app.queue_for(T::NAME, s.get_queue(), T::queue()).await;
+        let msg = Message::try_from(s)?.with_queue(queue.clone());
         let output = msg.serialize()?;
-        self.broker.enqueue(&self.queue, &output).await?;
+        let retention = Retention {
+            ttl: self.app.result_ttl(T::result_ttl()).await,
+            ignore: s.get_ignore_result(),
+        };
+        let queue = priority_queue(&queue, msg.get_priority());
+        TaskRecord::new(TaskState::Pending)
+            .save::<ClientError>(self.broker.as_ref(), &msg.get_id(), retention)
+            .await?;
+        match s.get_eta() {
+            Some(eta) if eta > SystemTime::now() => {
+                self.broker.enqueue_at(&queue, &output, eta).await?
+            }
+            _ => self.broker.enqueue(&queue, &output).await?,
+        }
 
-        Ok(AsyncResult::new(s))
+        Ok(AsyncResult::new(s, self.broker.clone()))
     }
 
-    pub async fn poll_result<T: AQTask>(
+    /// Revoke a submitted task: it is not run, or not run again if it is retried.
+    /// With `terminate`, a running task is also asked to stop through
+    /// its `TaskContext`, and ends as revoked whatever it returns.
+    pub async fn revoke<T: AQTask>(
         &self,
         result: &AsyncResult<T>,
-        to: Duration,
-    ) -> Result<TaskReturn<T::Returns>, ClientError> {
+        terminate: bool,
+    ) -> Result<(), ClientError> {
         let id = result.get_id();
-        let poll_fn = timeout(to, poll_fn(id, &self.broker));
-        match poll_fn.await {
-            Ok(Ok(res)) => {
-                let res: TaskReturn<T::Returns> = serde_json::from_str(&res).map_err(|e| e.into());
-                Ok(res)
-            }
-            Ok(Err(e)) => Err(e),
-            Err(e) => Err(e.into()),
+        let revocation = match terminate {
+            true => Revocation::Terminate,
+            false => Revocation::Skip,
+        };
+        let ttl = self.app.result_ttl(T::result_ttl()).await;
+        revoke::save(self.broker.as_ref(), &id, revocation, ttl).await?;
+
+        // no worker will write the final record of a task that is not running,
+        // one about to start it looks again once it saved it as started.
+        if let TaskState::Pending | TaskState::Retry = result.state().await? {
+            let retention = Retention {
+                ttl,
+                ignore: T::ignore_result(),
+            };
+            let mut record = TaskRecord::new(TaskState::Pending);
+            record.revoke();
+            record
+                .save::<ClientError>(self.broker.as_ref(), &id, retention)
+                .await?;
         }
+        Ok(())
     }
-}
 
-async fn poll_fn(id: String, broker: &Box<dyn Broker>) -> Result<String, ClientError> {
-    loop {
-        debug!("start polling");
-        match broker.get(&id).await? {
-            None => {
-                sleep(Duration::from_millis(1000)).await;
-            }
-            Some(res) => {
-                return Ok(res);
-            }
-        }
+    /// The current state of a submitted task.
+    pub async fn status<T: AQTask>(
+        &self,
+        result: &AsyncResult<T>,
+    ) -> Result<TaskState, ClientError> {
+        result.state().await
+    }
+
+    pub async fn poll_result<T: AQTask>(
+        &self,
+        result: &AsyncResult<T>,
+        to: Duration,
+    ) -> Result<TaskReturn<T::Returns>, ClientError> {
+        result.get(to).await
     }
 }
 
 pub struct Server {
     app: Arc<AsyncQueue>,
-    queue: String,
+    queues: Vec<String>,
+    policy: ConsumePolicy,
+    consumer: String,
+    visibility_timeout: Duration,
+    grace_period: Duration,
+    shutdown: ShutdownHandle,
+    signals: bool,
     broker: Box<dyn Broker>,
     timeout: u32,
     broker_builder: Arc<dyn BrokerBuilder>,
 }
 
 impl Server {
+    /// Set how long a dequeued task may stay unacknowledged
+    /// before it is handed out again. Defaults to one hour.
+    pub fn visibility_timeout(mut self, visibility_timeout: Duration) -> Self {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    /// Consume `queues` instead of the queue of the `AsyncQueue`.
+    /// Which one the next task is taken from is decided by the `ConsumePolicy`.
+    pub fn queues<Q: ToString>(mut self, queues: impl IntoIterator<Item = Q>) -> Self {
+        self.queues = queues.into_iter().map(|q| q.to_string()).collect();
+        self
+    }
+
+    /// Set how the next queue to consume is picked. Defaults to strict priority.
+    pub fn consume_policy(mut self, policy: ConsumePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set how long running tasks are waited for on shutdown,
+    /// before they are abandoned to other servers. Defaults to 30 seconds.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Shut the server down on SIGINT and SIGTERM,
+    /// warm on the first signal and cold on the next one.
+    pub fn handle_signals(mut self) -> Self {
+        self.signals = true;
+        self
+    }
+
+    /// A handle to shut the server down once started.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Run `num` workers until a warm shutdown is asked for:
+    /// no more tasks are reserved, the ones not started yet are given back,
+    /// and running ones are waited for up to the grace period.
+    /// A cold shutdown, or the end of the grace period, gives back
+    /// the running tasks as well.
     pub async fn start(&self, num: i32) -> Result<(), ServerError> {
-        info!("server start");
+        info!(
+            consumer = self.consumer,
+            "server start on {:?}", self.queues
+        );
         // channel for tasks
         let (tx, rx) = async_channel::bounded(num as usize);
         // channel indicate if worker is free
         let (token_tx, token_rx) = mpsc::channel(num as usize);
-        // channel for shutdown
-        let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
+        let in_flight = InFlight::default();
+        let mut workers = Vec::new();
         for i in 0..num {
-            let broker = self.broker_builder.build(self.timeout).await.unwrap();
-            let w = Worker::new(i, broker, self.app.clone());
+            let broker = self.broker_builder.build(self.timeout).await?;
+            let w = Worker::new(
+                i,
+                self.consumer.clone(),
+                self.visibility_timeout,
+                broker,
+                self.app.clone(),
+                in_flight.clone(),
+            );
 
             let rx = rx.clone();
             let token_tx = token_tx.clone();
-            let shutdown_tx = shutdown_tx.clone();
-            tokio::spawn(async move { w.start(rx, token_tx, shutdown_tx).await });
+            workers.push(tokio::spawn(async move { w.start(rx, token_tx).await }));
         }
         drop(token_tx);
-        drop(shutdown_tx);
+        let reaper = self.reap().await?;
+        let promoter = self.promote().await?;
+        let signals = match self.signals {
+            true => Some(self.shutdown.listen_signals()?),
+            false => None,
+        };
         self.schedule(tx, token_rx).await?;
 
-        // shutdown
-        let _ = shutdown_rx.recv().await;
+        // the scheduler is gone, so is the sender: tasks still in the channel
+        // were reserved but never started.
+        while let Ok(delivery) = rx.try_recv() {
+            self.release(&delivery).await;
+        }
+        drop(rx);
+
+        info!("waiting up to {:?} for running tasks", self.grace_period);
+        let drained = select! {
+            _ = futures::future::join_all(workers.iter_mut()) => true,
+            _ = sleep(self.grace_period) => {
+                warn!("grace period elapsed");
+                false
+            },
+            _ = self.shutdown.cold() => false,
+        };
+        if !drained {
+            info!("Cold shutdown...");
+            for worker in &workers {
+                worker.abort();
+            }
+            // workers that ended were already joined above.
+            for worker in workers.into_iter().filter(|w| !w.is_finished()) {
+                let _ = worker.await;
+            }
+            let abandoned: Vec<Delivery> =
+                in_flight.lock().unwrap().drain().map(|(_, d)| d).collect();
+            for delivery in &abandoned {
+                self.release(delivery).await;
+            }
+            let ids: Vec<String> = abandoned.iter().map(|d| d.task_id()).collect();
+            warn!("abandoned running tasks {:?}", ids);
+        }
+
+        reaper.abort();
+        promoter.abort();
+        if let Some(signals) = signals {
+            signals.abort();
+        }
         info!("server closed");
         Ok(())
     }
 
+    /// Give a reserved task back to its queue, for another server to run it.
+    async fn release(&self, delivery: &Delivery) {
+        if let Err(e) = self
+            .broker
+            .release(&delivery.queue, &self.consumer, &delivery.body)
+            .await
+        {
+            error!("fail to release task {}, {}", delivery.task_id(), e);
+        }
+    }
+
+    /// Spawn a loop requeueing tasks whose reservation expired,
+    /// e.g. because the server running them crashed.
+    async fn reap(&self) -> Result<JoinHandle<()>, ServerError> {
+        let broker = self.broker_builder.build(self.timeout).await?;
+        let queues = self.priority_queues();
+        Ok(tokio::spawn(async move {
+            loop {
+                match broker.requeue_expired(&queues).await {
+                    Ok(0) => {}
+                    Ok(n) => warn!("requeued {} expired tasks to {:?}", n, queues),
+                    Err(e) => error!("fail to requeue expired tasks of {:?}, {}", queues, e),
+                }
+                sleep(REAPER_INTERVAL).await;
+            }
+        }))
+    }
+
+    /// Spawn a loop moving delayed tasks onto the queue when they are due.
+    async fn promote(&self) -> Result<JoinHandle<()>, ServerError> {
+        let broker = self.broker_builder.build(self.timeout).await?;
+        let queues = self.priority_queues();
+        Ok(tokio::spawn(async move {
+            loop {
+                match broker.promote_due(&queues).await {
+                    Ok(0) => {}
+                    Ok(n) => debug!("promoted {} delayed tasks to {:?}", n, queues),
+                    Err(e) => error!("fail to promote delayed tasks of {:?}, {}", queues, e),
+                }
+                sleep(PROMOTE_INTERVAL).await;
+            }
+        }))
+    }
+
+    /// The broker queues behind the consumed queues, for every priority.
+    fn priority_queues(&self) -> Vec<String> {
+        self.queues
+            .iter()
+            .flat_map(|queue| (0..=MAX_PRIORITY).map(|p| priority_queue(queue, p)))
+            .collect()
+    }
+
+    /// Reserve a task from the first queue in `order` that has one,
+    /// taking the tasks of a queue by decreasing priority.
+    /// Every priority of every queue is checked by a single broker call.
+    async fn reserve_next(
+        &self,
+        order: Vec<usize>,
+    ) -> Result<Option<(String, String)>, BrokerError> {
+        let queues: Vec<String> = order
+            .into_iter()
+            .flat_map(|i| {
+                (0..=MAX_PRIORITY)
+                    .rev()
+                    .map(move |p| priority_queue(&self.queues[i], p))
+            })
+            .collect();
+        self.broker
+            .reserve(&queues, &self.consumer, self.visibility_timeout, QUEUE_WAIT)
+            .await
+    }
+
     async fn schedule(
         &self,
-        tx: async_channel::Sender<String>,
+        tx: async_channel::Sender<Delivery>,
         mut token_rx: mpsc::Receiver<()>,
     ) -> Result<(), ServerError> {
         // this is the flag indicate if we hold a token,
         // which means that there is a free worker waiting
         // and we are fine to poll a task from broker.
         let mut flag = false;
-        let mut ender = Ender::new()?;
+        let mut selector = Selector::new(self.policy.clone());
         info!("scheduler start");
         loop {
-            select! {
-                _ = token_rx.recv(), if !flag => {
-                    flag = true;
-                },
-                result = self.broker.dequeue(&self.queue), if flag => {
-                    match result {
-                        Ok(None) => {
-                            let _ = sleep(Duration::from_secs(1));
-                        }
-                        Ok(Some((queue, task))) => {
-                            flag = false;
-                            info!("got from queue {}, {}", queue, task);
-                            // TODO: error handle
-                            match tx.send(task).await {
-                                Ok(_) => {},
-                                Err(e) => error!("error dispatch task {}", e),
-                            }
-                        },
-                        Err(e) => {
-                            error!("got error when dequeue from broker {}, {}", self.queue, e.to_string());
-                        },
+            if !flag {
+                select! {
+                    _ = token_rx.recv() => flag = true,
+                    _ = self.shutdown.warm() => break,
+                }
+                continue;
+            }
+            // a reservation is never cancelled halfway: the broker may already
+            // hold the task for us, so finish it and give the task back if needed.
+            let order = selector.next_order(self.queues.len());
+            match self.reserve_next(order).await {
+                Ok(None) => {}
+                Ok(Some((queue, task))) => {
+                    selector.advance();
+                    let delivery = Delivery { queue, body: task };
+                    if self.shutdown.is_shutdown() {
+                        self.release(&delivery).await;
+                        break;
                     }
-                },
-                ending = ender.wait() => {
-                    if let Ok(SigType::Interrupt) = ending {
-                        warn!("got interrupt");
+                    flag = false;
+                    info!("got from queue {}, {}", delivery.queue, delivery.body);
+                    // TODO: error handle
+                    match tx.send(delivery).await {
+                        Ok(_) => {}
+                        Err(e) => error!("error dispatch task {}", e),
                     }
-                    info!("Warm shutdown...");
-                    break;
                 }
+                Err(e) => {
+                    error!(
+                        "got error when dequeue from broker {:?}, {}",
+                        self.queues,
+                        e.to_string()
+                    );
+                }
+            }
+            if self.shutdown.is_shutdown() {
+                break;
             }
         }
+        info!("Warm shutdown...");
         info!("scheduler closed");
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::app::context::TaskContext;
+    use crate::app::state::State;
+    use crate::error::TaskError;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub(crate) struct AddParams {
+        pub x: i32,
+        pub y: i32,
+    }
+
+    pub(crate) struct Add {
+        params: AddParams,
+    }
+
+    #[async_trait::async_trait]
+    impl AQTask for Add {
+        const NAME: &'static str = "add";
+        type Params = AddParams;
+        type Returns = i32;
+
+        async fn run(&self, _ctx: &TaskContext) -> TaskReturn<Self::Returns> {
+            Ok(self.params.x + self.params.y)
+        }
+        fn from_params(params: Self::Params, _: &StateMap) -> Result<Self, TracerError> {
+            Ok(Self { params })
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct PairsParams {}
+
+    /// Returns a map JSON cannot hold, its keys are not strings.
+    struct Pairs {}
+
+    #[async_trait::async_trait]
+    impl AQTask for Pairs {
+        const NAME: &'static str = "pairs";
+        type Params = PairsParams;
+        type Returns = HashMap<(i32, i32), i32>;
+
+        async fn run(&self, _ctx: &TaskContext) -> TaskReturn<Self::Returns> {
+            Ok(HashMap::from([((1, 2), 3)]))
+        }
+        fn from_params(_: Self::Params, _: &StateMap) -> Result<Self, TracerError> {
+            Ok(Self {})
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct PanicParams {}
+
+    struct Panic {}
+
+    #[async_trait::async_trait]
+    impl AQTask for Panic {
+        const NAME: &'static str = "panic";
+        type Params = PanicParams;
+        type Returns = i32;
+
+        async fn run(&self, _ctx: &TaskContext) -> TaskReturn<Self::Returns> {
+            panic!("boom")
+        }
+        fn from_params(_: Self::Params, _: &StateMap) -> Result<Self, TracerError> {
+            Ok(Self {})
+        }
+    }
+
+    struct Div {
+        params: AddParams,
+    }
+
+    #[async_trait::async_trait]
+    impl AQTask for Div {
+        const NAME: &'static str = "div";
+        type Params = AddParams;
+        type Returns = i32;
+
+        async fn run(&self, _ctx: &TaskContext) -> TaskReturn<Self::Returns> {
+            let AddParams { x, y } = self.params;
+            x.checked_div(y)
+                .ok_or_else(|| TaskError::failed("divide by zero".to_string()))
+        }
+        fn from_params(params: Self::Params, _: &StateMap) -> Result<Self, TracerError> {
+            Ok(Self { params })
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct SleepParams {
+        ms: u64,
+    }
+
+    struct Sleep {
+        params: SleepParams,
+    }
+
+    #[async_trait::async_trait]
+    impl AQTask for Sleep {
+        const NAME: &'static str = "sleep";
+        type Params = SleepParams;
+        type Returns = u64;
+
+        async fn run(&self, ctx: &TaskContext) -> TaskReturn<Self::Returns> {
+            select! {
+                _ = sleep(Duration::from_millis(self.params.ms)) => Ok(self.params.ms),
+                _ = ctx.cancelled() => Ok(0),
+                _ = ctx.soft_time_limit_exceeded() => Ok(1),
+            }
+        }
+        fn from_params(params: Self::Params, _: &StateMap) -> Result<Self, TracerError> {
+            Ok(Self { params })
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct ReportParams {
+        ms: u64,
+    }
+
+    /// Reports half of its work done, then tells where it ran.
+    struct Report {
+        params: ReportParams,
+    }
+
+    #[async_trait::async_trait]
+    impl AQTask for Report {
+        const NAME: &'static str = "report";
+        type Params = ReportParams;
+        type Returns = (String, String, u32, bool);
+
+        async fn run(&self, ctx: &TaskContext) -> TaskReturn<Self::Returns> {
+            ctx.report_progress(50).await;
+            sleep(Duration::from_millis(self.params.ms)).await;
+            Ok((
+                ctx.id().to_string(),
+                ctx.queue().to_string(),
+                ctx.retries(),
+                ctx.deadline().is_some(),
+            ))
+        }
+        fn from_params(params: Self::Params, _: &StateMap) -> Result<Self, TracerError> {
+            Ok(Self { params })
+        }
+    }
+
+    /// Adds the managed offset to its params.
+    struct Offset {
+        params: AddParams,
+        offset: State<i32>,
+    }
+
+    #[async_trait::async_trait]
+    impl AQTask for Offset {
+        const NAME: &'static str = "offset";
+        type Params = AddParams;
+        type Returns = i32;
+
+        async fn run(&self, _ctx: &TaskContext) -> TaskReturn<Self::Returns> {
+            Ok(self.params.x + self.params.y + *self.offset)
+        }
+        fn from_params(params: Self::Params, state: &StateMap) -> Result<Self, TracerError> {
+            Ok(Self {
+                params,
+                offset: state.get()?,
+            })
+        }
+    }
+
+    /// An app on the memory broker with one worker consuming `queues`.
+    /// Tasks can be registered afterwards, workers look them up for every message.
+    async fn serve_queues(queues: &[&str]) -> (Arc<AsyncQueue>, Client, ShutdownHandle) {
+        let aq = AsyncQueue::new("test", "test_queue", "memory://")
+            .await
+            .unwrap();
+        let client = aq.client().await.unwrap();
+        let server = aq.server().await.unwrap().queues(queues);
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(async move { server.start(1).await.unwrap() });
+        (aq, client, shutdown)
+    }
+
+    /// An app on the memory broker with one worker consuming its default queue.
+    async fn serve() -> (Arc<AsyncQueue>, Client, ShutdownHandle) {
+        serve_queues(&["test_queue"]).await
+    }
+
+    /// Run the tasks of `results` with a single worker of `server`,
+    /// returns their results in the order they finished.
+    async fn finish_order(
+        aq: &Arc<AsyncQueue>,
+        server: Server,
+        results: Vec<AsyncResult<Add>>,
+    ) -> Vec<i32> {
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(async move { server.start(1).await.unwrap() });
+        let broker = aq.broker_builder.build(10).await.unwrap();
+        let mut finished = Vec::new();
+        for result in results {
+            let x = result.get(Duration::from_secs(5)).await.unwrap().unwrap();
+            let raw = broker.get(&result.get_id()).await.unwrap().unwrap();
+            let record = TaskRecord::deserialize(&raw).unwrap();
+            finished.push((record.finished_at.unwrap(), x));
+        }
+        shutdown.shutdown();
+        finished.sort();
+        finished.into_iter().map(|(_, x)| x).collect()
+    }
+
+    /// Wait until the task of `result` was picked up by a worker.
+    async fn wait_started<T: AQTask>(result: &AsyncResult<T>) {
+        while result.state().await.unwrap() != TaskState::Started {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_broker_roundtrip() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Add>().await.unwrap();
+
+        let sig = Signature::<Add>::new(AddParams { x: 1, y: 2 });
+        let result = client.submit(&sig).await.unwrap();
+        let res = client
+            .poll_result(&result, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(3, res.unwrap());
+        assert_eq!(TaskState::Success, client.status(&result).await.unwrap());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_panic_is_isolated() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Add>().await.unwrap();
+        aq.register::<Panic>().await.unwrap();
+
+        let sig = Signature::<Panic>::new(PanicParams {});
+        let result = client.submit(&sig).await.unwrap();
+        let res = client
+            .poll_result(&result, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(TaskError::Panicked(msg)) if msg == "boom"));
+
+        // the only worker survived the panic
+        let sig = Signature::<Add>::new(AddParams { x: 1, y: 2 });
+        let result = client.submit(&sig).await.unwrap();
+        let res = client
+            .poll_result(&result, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(3, res.unwrap());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_task_failure() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Div>().await.unwrap();
+
+        let sig = Signature::<Div>::new(AddParams { x: 1, y: 0 });
+        let result = client.submit(&sig).await.unwrap();
+        let err = client
+            .poll_result(&result, Duration::from_secs(5))
+            .await
+            .unwrap()
+            .unwrap_err();
+        match &err {
+            TaskError::Failed { kind, message, .. } => {
+                assert_eq!("String", kind);
+                assert_eq!("divide by zero", message);
+            }
+            e => panic!("expect failure, got {:?}", e),
+        }
+        assert_eq!(Some("divide by zero".to_string()), err.data::<String>());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_unserializable_result() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Pairs>().await.unwrap();
+
+        let sig = Signature::<Pairs>::new(PairsParams {});
+        let result = client.submit(&sig).await.unwrap();
+        let res = result.get(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(res, Err(TaskError::Unserializable(_))));
+        assert_eq!(TaskState::Failure, result.state().await.unwrap());
+        // the task ran, its message is done with
+        let dead = aq.dead_letters().await.unwrap();
+        assert!(dead.list(0, 10).await.unwrap().is_empty());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_await_result() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Add>().await.unwrap();
+
+        let result = client
+            .submit(&Signature::<Add>::new(AddParams { x: 2, y: 3 }))
+            .await
+            .unwrap();
+        assert_eq!(
+            5,
+            result.get(Duration::from_secs(5)).await.unwrap().unwrap()
+        );
+        assert!(result.ready().await.unwrap());
+        let forgotten = result.get_id();
+        result.forget().await.unwrap();
+
+        let result = client
+            .submit(&Signature::<Add>::new(AddParams { x: 3, y: 4 }))
+            .await
+            .unwrap();
+        assert_eq!(7, result.await.unwrap().unwrap());
+        shutdown.shutdown();
+
+        let broker = aq.broker_builder.build(10).await.unwrap();
+        assert!(broker.get(&forgotten).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ignore_result() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Add>().await.unwrap();
+
+        let sig = Signature::<Add>::new(AddParams { x: 1, y: 2 }).ignore_result();
+        let ignored = client.submit(&sig).await.unwrap();
+        let sig = Signature::<Add>::new(AddParams { x: 2, y: 2 });
+        let result = client.submit(&sig).await.unwrap();
+        assert_eq!(
+            4,
+            result.get(Duration::from_secs(5)).await.unwrap().unwrap()
+        );
+        shutdown.shutdown();
+
+        // tasks run in order on the only worker, so the ignored one is done.
+        let broker = aq.broker_builder.build(10).await.unwrap();
+        assert!(broker.get(&ignored.get_id()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_countdown() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Add>().await.unwrap();
+
+        let countdown = Duration::from_secs(1);
+        let submitted = SystemTime::now();
+        let sig = Signature::<Add>::new(AddParams { x: 1, y: 2 }).countdown(countdown);
+        let result = client.submit(&sig).await.unwrap();
+        assert_eq!(TaskState::Pending, result.state().await.unwrap());
+        assert_eq!(
+            3,
+            result.get(Duration::from_secs(5)).await.unwrap().unwrap()
+        );
+        assert!(submitted.elapsed().unwrap() >= countdown);
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_expires() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Add>().await.unwrap();
+
+        let sig = Signature::<Add>::new(AddParams { x: 1, y: 2 })
+            .expires(SystemTime::now() - Duration::from_secs(1));
+        let expired = client.submit(&sig).await.unwrap();
+        let sig = Signature::<Add>::new(AddParams { x: 2, y: 2 }).expires(Duration::from_secs(60));
+        let result = client.submit(&sig).await.unwrap();
+        assert_eq!(
+            4,
+            result.get(Duration::from_secs(5)).await.unwrap().unwrap()
+        );
+        let res = expired.get(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(res, Err(TaskError::Expired)));
+        assert_eq!(TaskState::Expired, expired.state().await.unwrap());
+
+        // expired before the lookup of a task this server does not know
+        let sig = Signature::<Div>::new(AddParams { x: 1, y: 1 })
+            .expires(SystemTime::now() - Duration::from_secs(1));
+        let unknown = client.submit(&sig).await.unwrap();
+        let res = unknown.get(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(res, Err(TaskError::Expired)));
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_queue_for() {
+        let aq = AsyncQueue::new("test", "default", "memory://")
+            .await
+            .unwrap();
+        aq.route("report.*", "slow").await;
+        aq.route("add", "fast").await;
+
+        assert_eq!("default", aq.queue_for("mul", None, None).await);
+        assert_eq!("math", aq.queue_for("mul", None, Some("math")).await);
+        assert_eq!("fast", aq.queue_for("add", None, Some("math")).await);
+        assert_eq!("slow", aq.queue_for("report.daily", None, None).await);
+        let sig_queue = Some("urgent".to_string());
+        assert_eq!("urgent", aq.queue_for("add", sig_queue, None).await);
+    }
+
+    #[tokio::test]
+    async fn test_queues() {
+        let (aq, client, shutdown) = serve_queues(&["fast", "slow"]).await;
+        aq.register::<Add>().await.unwrap();
+        aq.route("add", "fast").await;
+
+        let sig = Signature::<Add>::new(AddParams { x: 1, y: 2 });
+        let routed = client.submit(&sig).await.unwrap();
+        let sig = Signature::<Add>::new(AddParams { x: 2, y: 2 }).queue("slow");
+        let overridden = client.submit(&sig).await.unwrap();
+        let to = Duration::from_secs(5);
+        assert_eq!(3, routed.get(to).await.unwrap().unwrap());
+        assert_eq!(4, overridden.get(to).await.unwrap().unwrap());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_priority() {
+        let aq = AsyncQueue::new("test", "test_queue", "memory://")
+            .await
+            .unwrap();
+        aq.register::<Add>().await.unwrap();
+
+        let client = aq.client().await.unwrap();
+        let mut results = Vec::new();
+        for (x, priority) in [(0, 0), (1, 0), (2, 5), (3, 9)] {
+            let sig = Signature::<Add>::new(AddParams { x, y: 0 }).priority(priority);
+            results.push(client.submit(&sig).await.unwrap());
+        }
+
+        // the only worker runs them one by one, most urgent first
+        let server = aq.server().await.unwrap();
+        let order = finish_order(&aq, server, results).await;
+        assert_eq!(vec![3, 2, 0, 1], order);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_round_robin() {
+        let aq = AsyncQueue::new("test", "test_queue", "memory://")
+            .await
+            .unwrap();
+        aq.register::<Add>().await.unwrap();
+
+        let client = aq.client().await.unwrap();
+        let mut results = Vec::new();
+        for (queue, offset) in [("a", 0), ("b", 100)] {
+            for x in 0..6 {
+                let sig = Signature::<Add>::new(AddParams {
+                    x: offset + x,
+                    y: 0,
+                })
+                .queue(queue);
+                results.push(client.submit(&sig).await.unwrap());
+            }
+        }
+
+        let policy = ConsumePolicy::WeightedRoundRobin(vec![1, 1]);
+        let server = aq
+            .server()
+            .await
+            .unwrap()
+            .queues(["a", "b"])
+            .consume_policy(policy);
+        // the queues take turns on the only worker
+        let order = finish_order(&aq, server, results).await;
+        assert_eq!(vec![0, 100, 1, 101, 2, 102, 3, 103, 4, 104, 5, 105], order);
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Div>().await.unwrap();
+
+        let broker = aq.broker_builder.build(10).await.unwrap();
+        broker.enqueue("test_queue", "not a message").await.unwrap();
+        let unknown = client
+            .submit(&Signature::<Add>::new(AddParams { x: 1, y: 2 }))
+            .await
+            .unwrap();
+        let policy = retry::RetryPolicy::new(
+            1,
+            retry::Backoff::Fixed {
+                delay: Duration::from_millis(10),
+            },
+        );
+        let sig = Signature::<Div>::new(AddParams { x: 1, y: 0 }).retry_policy(policy);
+        let failed = client.submit(&sig).await.unwrap();
+        let res = failed.get(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(res, Err(TaskError::Failed { .. })));
+
+        // the failure is stored right before the message is dead-lettered
+        let dead = aq.dead_letters().await.unwrap();
+        let mut letters = dead.list(0, 10).await.unwrap();
+        while letters.len() < 3 {
+            sleep(Duration::from_millis(10)).await;
+            letters = dead.list(0, 10).await.unwrap();
+        }
+        assert!(letters[0].message().is_none());
+        assert!(letters[2].reason.starts_with("exhausted 1 retries"));
+
+        // registering the missing task and requeueing its letter runs it
+        let letter = dead.inspect(&unknown.get_id()).await.unwrap().unwrap();
+        assert_eq!("test_queue", letter.queue);
+        aq.register::<Add>().await.unwrap();
+        assert!(dead.requeue(&letter).await.unwrap());
+        assert!(!dead.requeue(&letter).await.unwrap());
+        assert_eq!(
+            3,
+            unknown.get(Duration::from_secs(5)).await.unwrap().unwrap()
+        );
+
+        assert_eq!(2, dead.purge().await.unwrap());
+        shutdown.shutdown();
+
+        aq.set_dead_letter_queue(None).await;
+        assert!(matches!(
+            aq.dead_letters().await,
+            Err(QueueError::DeadLetterDisabled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_warm_shutdown() {
+        let aq = AsyncQueue::new("test", "test_queue", "memory://")
+            .await
+            .unwrap();
+        aq.register::<Sleep>().await.unwrap();
+
+        let client = aq.client().await.unwrap();
+        let server = aq.server().await.unwrap();
+        let handle = server.shutdown_handle();
+        let h = tokio::spawn(async move { server.start(1).await });
+
+        let running = client
+            .submit(&Signature::<Sleep>::new(SleepParams { ms: 100 }))
+            .await
+            .unwrap();
+        wait_started(&running).await;
+        let pending = client
+            .submit(&Signature::<Sleep>::new(SleepParams { ms: 0 }))
+            .await
+            .unwrap();
+        handle.shutdown();
+        h.await.unwrap().unwrap();
+
+        // the running task was waited for, the other one left for later
+        assert_eq!(TaskState::Success, running.state().await.unwrap());
+        assert_eq!(TaskState::Pending, pending.state().await.unwrap());
+        let broker = aq.broker_builder.build(10).await.unwrap();
+        assert_eq!(1, broker.peek("test_queue", 0, 10).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_long_task_stays_reserved() {
+        let aq = AsyncQueue::new("test", "test_queue", "memory://")
+            .await
+            .unwrap();
+        aq.register::<Sleep>().await.unwrap();
+
+        let client = aq.client().await.unwrap();
+        let server = aq
+            .server()
+            .await
+            .unwrap()
+            .visibility_timeout(Duration::from_millis(200));
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(async move { server.start(1).await.unwrap() });
+
+        let result = client
+            .submit(&Signature::<Sleep>::new(SleepParams { ms: 600 }))
+            .await
+            .unwrap();
+        wait_started(&result).await;
+        sleep(Duration::from_millis(400)).await;
+        // past the visibility timeout, but the reservation was extended
+        let broker = aq.broker_builder.build(10).await.unwrap();
+        let queues = ["test_queue".to_string()];
+        assert_eq!(0, broker.requeue_expired(&queues).await.unwrap());
+        assert_eq!(
+            600,
+            result.get(Duration::from_secs(5)).await.unwrap().unwrap()
+        );
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_cold_shutdown() {
+        let aq = AsyncQueue::new("test", "test_queue", "memory://")
+            .await
+            .unwrap();
+        aq.register::<Sleep>().await.unwrap();
+
+        let client = aq.client().await.unwrap();
+        let server = aq.server().await.unwrap();
+        let handle = server.shutdown_handle();
+        let h = tokio::spawn(async move { server.start(1).await });
+
+        let result = client
+            .submit(&Signature::<Sleep>::new(SleepParams { ms: 10_000 }))
+            .await
+            .unwrap();
+        wait_started(&result).await;
+        handle.shutdown();
+        handle.shutdown();
+        h.await.unwrap().unwrap();
+
+        // the abandoned task is back in the queue for another server
+        let broker = aq.broker_builder.build(10).await.unwrap();
+        let queued = broker.peek("test_queue", 0, 10).await.unwrap();
+        assert_eq!(1, queued.len());
+        assert!(queued[0].contains(&result.get_id()));
+        assert_eq!(
+            0,
+            broker
+                .requeue_expired(&["test_queue".to_string()])
+                .await
+                .unwrap()
+        );
+
+        // as it is for an elapsed grace period
+        let server = aq.server().await.unwrap().grace_period(Duration::ZERO);
+        let handle = server.shutdown_handle();
+        let h = tokio::spawn(async move { server.start(1).await });
+        while !broker.peek("test_queue", 0, 10).await.unwrap().is_empty() {
+            sleep(Duration::from_millis(5)).await;
+        }
+        handle.shutdown();
+        h.await.unwrap().unwrap();
+        assert_eq!(1, broker.peek("test_queue", 0, 10).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_revoke() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Sleep>().await.unwrap();
+
+        let running = client
+            .submit(&Signature::<Sleep>::new(SleepParams { ms: 10_000 }))
+            .await
+            .unwrap();
+        wait_started(&running).await;
+        let pending = client
+            .submit(&Signature::<Sleep>::new(SleepParams { ms: 0 }))
+            .await
+            .unwrap();
+
+        client.revoke(&pending, false).await.unwrap();
+        assert_eq!(TaskState::Revoked, pending.state().await.unwrap());
+        client.revoke(&running, true).await.unwrap();
+        let res = running.get(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(res, Err(TaskError::Revoked)));
+
+        // the worker is free again and skips the revoked task
+        let next = client
+            .submit(&Signature::<Sleep>::new(SleepParams { ms: 0 }))
+            .await
+            .unwrap();
+        assert_eq!(0, next.get(Duration::from_secs(5)).await.unwrap().unwrap());
+        let res = pending.get(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(res, Err(TaskError::Revoked)));
+        let raw = aq
+            .broker_builder
+            .build(10)
+            .await
+            .unwrap()
+            .get(&pending.get_id())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(TaskRecord::deserialize(&raw).unwrap().started_at.is_none());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_time_limits() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Sleep>().await.unwrap();
+
+        let sig = Signature::<Sleep>::new(SleepParams { ms: 10_000 })
+            .soft_time_limit(Duration::from_millis(50));
+        let res = client.submit(&sig).await.unwrap();
+        assert_eq!(1, res.get(Duration::from_secs(5)).await.unwrap().unwrap());
+
+        let sig = Signature::<Sleep>::new(SleepParams { ms: 10_000 })
+            .time_limit(Duration::from_millis(50));
+        let res = client.submit(&sig).await.unwrap();
+        let res = res.get(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(res, Err(TaskError::TimeLimitExceeded(_))));
+
+        // the worker is free again
+        let sig = Signature::<Sleep>::new(SleepParams { ms: 0 });
+        let res = client.submit(&sig).await.unwrap();
+        assert_eq!(0, res.get(Duration::from_secs(5)).await.unwrap().unwrap());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_context() {
+        let (aq, client, shutdown) = serve_queues(&["reports"]).await;
+        aq.register::<Report>().await.unwrap();
+
+        let sig = Signature::<Report>::new(ReportParams { ms: 200 })
+            .queue("reports")
+            .priority(5)
+            .time_limit(Duration::from_secs(10));
+        let res = client.submit(&sig).await.unwrap();
+        while res.progress::<u32>().await.unwrap().is_none() {
+            sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(TaskState::Started, res.state().await.unwrap());
+        let (id, queue, retries, deadline) =
+            res.get(Duration::from_secs(5)).await.unwrap().unwrap();
+        assert_eq!(res.get_id(), id);
+        assert_eq!("reports", queue);
+        assert_eq!(0, retries);
+        assert!(deadline);
+        assert_eq!(Some(50), res.progress::<u32>().await.unwrap());
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_state() {
+        let (aq, client, shutdown) = serve().await;
+        aq.register::<Offset>().await.unwrap();
+
+        // without its state the task cannot be built, whichever worker tries
+        let sig = Signature::<Offset>::new(AddParams { x: 1, y: 2 });
+        let res = client.submit(&sig).await.unwrap();
+        let dead = aq.dead_letters().await.unwrap();
+        let mut letters = dead.list(0, 10).await.unwrap();
+        while letters.is_empty() {
+            sleep(Duration::from_millis(10)).await;
+            letters = dead.list(0, 10).await.unwrap();
+        }
+        assert!(letters[0].reason.contains("no state of type i32"));
+
+        aq.manage(10).await;
+        assert!(dead.requeue(&letters[0]).await.unwrap());
+        assert_eq!(13, res.get(Duration::from_secs(5)).await.unwrap().unwrap());
+        shutdown.shutdown();
+    }
+}
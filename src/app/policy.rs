@@ -0,0 +1,122 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+/// How a server consuming several queues picks the one to take the next task from.
+#[derive(Clone, Debug, Default)]
+pub enum ConsumePolicy {
+    /// The first queue that has a task, in the given order.
+    /// Later queues starve as long as earlier ones are busy.
+    #[default]
+    Priority,
+    /// Queues take turns, each one being preferred as many times in a row
+    /// as its weight, given in the order of the queues. Missing weights are 1.
+    WeightedRoundRobin(Vec<u32>),
+    /// A queue drawn at random, every queue having the same chance.
+    Random,
+}
+
+/// Decides in which order the queues are tried, following a `ConsumePolicy`.
+pub(crate) struct Selector {
+    policy: ConsumePolicy,
+    cursor: usize,
+    rng: StdRng,
+}
+
+impl Selector {
+    pub fn new(policy: ConsumePolicy) -> Self {
+        Selector::with_rng(policy, StdRng::from_entropy())
+    }
+
+    fn with_rng(policy: ConsumePolicy, rng: StdRng) -> Self {
+        Selector {
+            policy,
+            cursor: 0,
+            rng,
+        }
+    }
+
+    /// Indexes of `len` queues in the order to try them for the next task.
+    /// Queues skipped because they are empty still come after the preferred one,
+    /// so no task waits while a worker is free.
+    ///
+    /// The same queue stays preferred until `advance` is called.
+    pub fn next_order(&mut self, len: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..len).collect();
+        match &self.policy {
+            ConsumePolicy::Priority => {}
+            ConsumePolicy::WeightedRoundRobin(weights) => {
+                let turns: Vec<usize> = (0..len)
+                    .flat_map(|i| {
+                        let weight = weights.get(i).copied().unwrap_or(1);
+                        std::iter::repeat_n(i, weight as usize)
+                    })
+                    .collect();
+                if !turns.is_empty() {
+                    let preferred = turns[self.cursor % turns.len()];
+                    order.remove(preferred);
+                    order.insert(0, preferred);
+                }
+            }
+            ConsumePolicy::Random => order.shuffle(&mut self.rng),
+        }
+        order
+    }
+
+    /// Move on to the next turn, once a task was taken.
+    pub fn advance(&mut self) {
+        self.cursor = self.cursor.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preferred(selector: &mut Selector, len: usize, n: usize) -> Vec<usize> {
+        (0..n)
+            .map(|_| {
+                let preferred = selector.next_order(len)[0];
+                selector.advance();
+                preferred
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut selector = Selector::new(ConsumePolicy::Priority);
+        assert_eq!(vec![0, 1, 2], selector.next_order(3));
+        assert_eq!(vec![0, 1, 2], selector.next_order(3));
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let policy = ConsumePolicy::WeightedRoundRobin(vec![2, 1]);
+        let mut selector = Selector::new(policy);
+        assert_eq!(vec![0, 0, 1, 0, 0, 1], preferred(&mut selector, 2, 6));
+        // no turn is taken until a task is
+        assert_eq!(vec![0, 1], selector.next_order(2));
+        assert_eq!(vec![0, 1], selector.next_order(2));
+
+        // a zero weight is never preferred but still tried
+        let policy = ConsumePolicy::WeightedRoundRobin(vec![0]);
+        let mut selector = Selector::new(policy);
+        assert_eq!(vec![1, 1], preferred(&mut selector, 2, 2));
+        assert_eq!(vec![1, 0], selector.next_order(2));
+    }
+
+    #[test]
+    fn test_random() {
+        let rng = StdRng::seed_from_u64(7);
+        let mut selector = Selector::with_rng(ConsumePolicy::Random, rng);
+        let picks = preferred(&mut selector, 3, 300);
+        for queue in 0..3 {
+            let count = picks.iter().filter(|&&p| p == queue).count();
+            assert!(count > 50, "queue {} picked {} times", queue, count);
+        }
+        let mut order = selector.next_order(3);
+        order.sort();
+        assert_eq!(vec![0, 1, 2], order);
+    }
+}
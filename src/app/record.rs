@@ -0,0 +1,204 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+use super::task::TaskReturn;
+use crate::broker::Broker;
+use crate::error::{BrokerError, MsgError, TaskError};
+
+/// How long the record of a task is kept in the broker.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Retention {
+    pub ttl: Option<Duration>,
+    /// never store anything, for fire-and-forget tasks
+    pub ignore: bool,
+}
+
+/// Where a task is in its lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskState {
+    /// Submitted, or unknown to the broker.
+    Pending,
+    /// Picked up by a worker.
+    Started,
+    /// Failed and waiting for another attempt.
+    Retry,
+    Success,
+    Failure,
+    /// Dropped because it was not started before it expired.
+    Expired,
+    /// Revoked by a client before it finished.
+    Revoked,
+}
+
+impl TaskState {
+    /// Whether the task reached a final state and will not change anymore.
+    pub fn is_ready(&self) -> bool {
+        matches!(
+            self,
+            TaskState::Success | TaskState::Failure | TaskState::Expired | TaskState::Revoked
+        )
+    }
+}
+
+/// What the broker stores under a task id, rewritten at each state transition.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskRecord {
+    pub state: TaskState,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<TaskError>,
+    /// id of the worker that ran the task last
+    pub worker: Option<String>,
+    pub retries: u32,
+    /// the last progress reported by the running task
+    #[serde(default)]
+    pub progress: Option<serde_json::Value>,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+    pub updated_at: SystemTime,
+}
+
+impl TaskRecord {
+    pub fn new(state: TaskState) -> Self {
+        TaskRecord {
+            state,
+            result: None,
+            error: None,
+            worker: None,
+            retries: 0,
+            progress: None,
+            started_at: None,
+            finished_at: None,
+            updated_at: SystemTime::now(),
+        }
+    }
+
+    pub fn serialize(&self) -> Result<String, MsgError> {
+        serde_json::to_string(self).map_err(|e| e.into())
+    }
+
+    pub fn deserialize(val: &str) -> Result<Self, MsgError> {
+        serde_json::from_str(val).map_err(|e| e.into())
+    }
+
+    /// Write the record under `id` according to `retention`,
+    /// waking up clients waiting for it when the task is ready.
+    pub(crate) async fn save<E>(
+        &self,
+        broker: &dyn Broker,
+        id: &str,
+        retention: Retention,
+    ) -> Result<(), E>
+    where
+        E: From<BrokerError> + From<MsgError>,
+    {
+        if retention.ignore {
+            return Ok(());
+        }
+        let val = self.serialize()?;
+        broker.set(id, &val, retention.ttl).await?;
+        if self.state.is_ready() {
+            broker.notify(id, retention.ttl).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn started(worker: String, retries: u32) -> Self {
+        let mut record = TaskRecord::new(TaskState::Started);
+        record.worker = Some(worker);
+        record.retries = retries;
+        record.started_at = Some(record.updated_at);
+        record
+    }
+
+    pub(crate) fn report(&mut self, progress: serde_json::Value) {
+        self.updated_at = SystemTime::now();
+        self.progress = Some(progress);
+    }
+
+    pub(crate) fn retry(&mut self, err: TaskError) {
+        self.transit(TaskState::Retry);
+        self.error = Some(err);
+    }
+
+    pub(crate) fn succeed(&mut self, result: serde_json::Value) {
+        self.transit(TaskState::Success);
+        self.finished_at = Some(self.updated_at);
+        self.result = Some(result);
+        self.error = None;
+    }
+
+    pub(crate) fn fail(&mut self, err: TaskError) {
+        self.transit(TaskState::Failure);
+        self.finished_at = Some(self.updated_at);
+        self.error = Some(err);
+    }
+
+    pub(crate) fn expire(&mut self) {
+        self.transit(TaskState::Expired);
+        self.finished_at = Some(self.updated_at);
+        self.error = Some(TaskError::Expired);
+    }
+
+    pub(crate) fn revoke(&mut self) {
+        self.transit(TaskState::Revoked);
+        self.finished_at = Some(self.updated_at);
+        self.result = None;
+        self.error = Some(TaskError::Revoked);
+    }
+
+    fn transit(&mut self, state: TaskState) {
+        self.state = state;
+        self.updated_at = SystemTime::now();
+    }
+
+    /// The typed outcome of a finished task.
+    pub fn into_return<R: DeserializeOwned>(self) -> TaskReturn<R> {
+        match (self.state, self.error) {
+            (TaskState::Failure | TaskState::Expired | TaskState::Revoked, Some(e)) => Err(e),
+            _ => {
+                let result = self.result.unwrap_or_default();
+                serde_json::from_value(result).map_err(|e| e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let mut record = TaskRecord::started("w".into(), 1);
+        assert_eq!(TaskState::Started, record.state);
+        assert!(!record.state.is_ready());
+
+        record.retry(TaskError::Panicked("boom".into()));
+        assert_eq!(TaskState::Retry, record.state);
+
+        record.succeed(serde_json::json!(3));
+        assert!(record.state.is_ready());
+        assert!(record.finished_at.is_some());
+
+        let raw = record.serialize().unwrap();
+        let record = TaskRecord::deserialize(&raw).unwrap();
+        assert_eq!(3, record.into_return::<i32>().unwrap());
+    }
+
+    #[test]
+    fn test_failure() {
+        let mut record = TaskRecord::started("w".into(), 0);
+        record.fail(TaskError::Panicked("boom".into()));
+        let res = record.into_return::<i32>();
+        assert!(matches!(res, Err(TaskError::Panicked(msg)) if msg == "boom"));
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut record = TaskRecord::new(TaskState::Pending);
+        record.expire();
+        assert!(record.state.is_ready());
+        let res = record.into_return::<i32>();
+        assert!(matches!(res, Err(TaskError::Expired)));
+    }
+}
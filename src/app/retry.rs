@@ -0,0 +1,112 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long to wait before the next attempt of a failed task.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Backoff {
+    /// Always wait `delay`.
+    Fixed { delay: Duration },
+    /// Wait `base * 2^retries`, capped at `max`.
+    Exponential { base: Duration, max: Duration },
+    /// Wait a random duration between zero and the exponential delay ("full jitter").
+    Jittered { base: Duration, max: Duration },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::Fixed {
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay before the attempt following `retries` earlier retries.
+    pub fn delay(&self, retries: u32) -> Duration {
+        match *self {
+            Backoff::Fixed { delay } => delay,
+            Backoff::Exponential { base, max } => exponential(base, max, retries),
+            Backoff::Jittered { base, max } => {
+                let cap = exponential(base, max, retries);
+                cap.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+            }
+        }
+    }
+}
+
+fn exponential(base: Duration, max: Duration, retries: u32) -> Duration {
+    let factor = 2u32.saturating_pow(retries);
+    base.checked_mul(factor).unwrap_or(max).min(max)
+}
+
+/// Decides whether and when a failed task is run again.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Backoff,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, backoff: Backoff) -> Self {
+        RetryPolicy {
+            max_retries,
+            backoff,
+        }
+    }
+
+    /// The delay before the next attempt,
+    /// or `None` if `retries` already exhausted the policy.
+    pub fn next_delay(&self, retries: u32) -> Option<Duration> {
+        if retries >= self.max_retries {
+            return None;
+        }
+        Some(self.backoff.delay(retries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed() {
+        let policy = RetryPolicy::new(
+            2,
+            Backoff::Fixed {
+                delay: Duration::from_secs(3),
+            },
+        );
+        assert_eq!(Some(Duration::from_secs(3)), policy.next_delay(0));
+        assert_eq!(Some(Duration::from_secs(3)), policy.next_delay(1));
+        assert_eq!(None, policy.next_delay(2));
+    }
+
+    #[test]
+    fn test_exponential() {
+        let backoff = Backoff::Exponential {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+        };
+        assert_eq!(Duration::from_secs(1), backoff.delay(0));
+        assert_eq!(Duration::from_secs(4), backoff.delay(2));
+        assert_eq!(Duration::from_secs(10), backoff.delay(4));
+        assert_eq!(Duration::from_secs(10), backoff.delay(100));
+    }
+
+    #[test]
+    fn test_jittered() {
+        let backoff = Backoff::Jittered {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+        };
+        for retries in 0..10 {
+            assert!(backoff.delay(retries) <= Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn test_default_never_retries() {
+        assert_eq!(None, RetryPolicy::default().next_delay(0));
+    }
+}
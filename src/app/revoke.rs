@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use crate::broker::Broker;
+use crate::error::BrokerError;
+
+/// How often a worker checks whether the task it runs was terminated.
+pub(crate) const REVOKE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What was asked for a revoked task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Revocation {
+    /// do not run it, or not again
+    Skip,
+    /// stop it as well if it is running
+    Terminate,
+}
+
+fn revoke_key(id: &str) -> String {
+    format!("{}:revoked", id)
+}
+
+/// Record that the task `id` is revoked, for `ttl` if given.
+pub(crate) async fn save(
+    broker: &dyn Broker,
+    id: &str,
+    revocation: Revocation,
+    ttl: Option<Duration>,
+) -> Result<(), BrokerError> {
+    let val = match revocation {
+        Revocation::Skip => "skip",
+        Revocation::Terminate => "terminate",
+    };
+    broker.set(&revoke_key(id), val, ttl).await
+}
+
+/// Whether the task `id` is revoked, and how.
+pub(crate) async fn get(broker: &dyn Broker, id: &str) -> Result<Option<Revocation>, BrokerError> {
+    let revocation = broker
+        .get(&revoke_key(id))
+        .await?
+        .map(|val| match &val[..] {
+            "terminate" => Revocation::Terminate,
+            _ => Revocation::Skip,
+        });
+    Ok(revocation)
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use super::signal::{Ender, SigType};
+
+/// How far a shutdown went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Running,
+    /// stop taking tasks, let the running ones finish
+    Warm,
+    /// give the running tasks up
+    Cold,
+}
+
+/// Stops a `Server` or a `Beat` from anywhere, e.g. a test or the host application.
+///
+/// Clones control the same server.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    stage: Arc<watch::Sender<Stage>>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        ShutdownHandle {
+            stage: Arc::new(watch::Sender::new(Stage::Running)),
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Ask for a warm shutdown, or for a cold one if a warm one was already asked for.
+    pub fn shutdown(&self) {
+        self.stage.send_modify(|stage| {
+            *stage = match stage {
+                Stage::Running => Stage::Warm,
+                _ => Stage::Cold,
+            }
+        });
+    }
+
+    /// Ask for a cold shutdown right away.
+    pub fn force(&self) {
+        self.stage.send_replace(Stage::Cold);
+    }
+
+    /// Whether a shutdown was asked for.
+    pub fn is_shutdown(&self) -> bool {
+        *self.stage.borrow() != Stage::Running
+    }
+
+    /// Wait until a shutdown is asked for.
+    pub(crate) async fn warm(&self) {
+        self.wait_for(Stage::Warm).await
+    }
+
+    /// Wait until a cold shutdown is asked for.
+    pub(crate) async fn cold(&self) {
+        self.wait_for(Stage::Cold).await
+    }
+
+    async fn wait_for(&self, stage: Stage) {
+        let mut rx = self.stage.subscribe();
+        // the sender lives as long as `self`, so this never fails.
+        let _ = rx.wait_for(|s| *s >= stage).await;
+    }
+
+    /// Turn SIGINT and SIGTERM into shutdowns: the first one is warm, the next one cold.
+    pub(crate) fn listen_signals(&self) -> Result<JoinHandle<()>, std::io::Error> {
+        let mut ender = Ender::new()?;
+        let handle = self.clone();
+        Ok(tokio::spawn(async move {
+            while let Ok(sig) = ender.wait().await {
+                if let SigType::Interrupt = sig {
+                    warn!("got interrupt");
+                }
+                handle.shutdown();
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_stages() {
+        let handle = ShutdownHandle::default();
+        let to = Duration::from_millis(10);
+        assert!(!handle.is_shutdown());
+        assert!(timeout(to, handle.warm()).await.is_err());
+
+        handle.shutdown();
+        assert!(handle.is_shutdown());
+        assert!(timeout(to, handle.warm()).await.is_ok());
+        assert!(timeout(to, handle.cold()).await.is_err());
+
+        handle.clone().shutdown();
+        assert!(timeout(to, handle.cold()).await.is_ok());
+    }
+}
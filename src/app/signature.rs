@@ -1,4 +1,6 @@
-use super::AQTask;
+use super::retry::RetryPolicy;
+use super::{AQTask, MAX_PRIORITY};
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -8,6 +10,41 @@ where
 {
     id: String,
     params: T::Params,
+    retry_policy: Option<RetryPolicy>,
+    ignore_result: bool,
+    eta: Option<SystemTime>,
+    expires: Option<SystemTime>,
+    queue: Option<String>,
+    priority: u8,
+    time_limit: Option<Duration>,
+    soft_time_limit: Option<Duration>,
+}
+
+/// A point in time, given either from now or as is.
+pub enum Deadline {
+    After(Duration),
+    At(SystemTime),
+}
+
+impl From<Duration> for Deadline {
+    fn from(d: Duration) -> Self {
+        Deadline::After(d)
+    }
+}
+
+impl From<SystemTime> for Deadline {
+    fn from(at: SystemTime) -> Self {
+        Deadline::At(at)
+    }
+}
+
+impl Deadline {
+    fn at(self) -> SystemTime {
+        match self {
+            Deadline::After(d) => SystemTime::now() + d,
+            Deadline::At(at) => at,
+        }
+    }
 }
 
 impl<T> Signature<T>
@@ -19,7 +56,67 @@ where
         Self::new_with_id(Uuid::new_v4().to_string(), params)
     }
     pub fn new_with_id(id: String, params: T::Params) -> Self {
-        Self { id, params }
+        Self {
+            id,
+            params,
+            retry_policy: None,
+            ignore_result: false,
+            eta: None,
+            expires: None,
+            queue: None,
+            priority: 0,
+            time_limit: None,
+            soft_time_limit: None,
+        }
+    }
+
+    /// Override the retry policy declared on the task.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Run the task no earlier than `countdown` from now.
+    pub fn countdown(self, countdown: Duration) -> Self {
+        self.eta(SystemTime::now() + countdown)
+    }
+
+    /// Run the task no earlier than `eta`.
+    pub fn eta(mut self, eta: SystemTime) -> Self {
+        self.eta = Some(eta);
+        self
+    }
+
+    /// Drop the task instead of running it if it was not started by then,
+    /// either a `Duration` from now or a `SystemTime`.
+    pub fn expires(mut self, expires: impl Into<Deadline>) -> Self {
+        self.expires = Some(expires.into().at());
+        self
+    }
+
+    /// Send the task to `queue`, whatever the task or the routing rules say.
+    pub fn queue(mut self, queue: impl ToString) -> Self {
+        self.queue = Some(queue.to_string());
+        self
+    }
+
+    /// Run the task ahead of the ones of its queue with a lower priority.
+    /// Defaults to 0, the lowest, and is capped to `MAX_PRIORITY`.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority.min(MAX_PRIORITY);
+        self
+    }
+
+    /// Override the hard time limit declared on the task.
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Override the soft time limit declared on the task.
+    pub fn soft_time_limit(mut self, limit: Duration) -> Self {
+        self.soft_time_limit = Some(limit);
+        self
     }
 
     pub fn get_id(&self) -> String {
@@ -28,6 +125,36 @@ where
     pub fn get_params(&self) -> T::Params {
         self.params.clone()
     }
+    /// Do not store the result of this call, whatever the task declares.
+    pub fn ignore_result(mut self) -> Self {
+        self.ignore_result = true;
+        self
+    }
+
+    pub fn get_retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry_policy.clone()
+    }
+    pub fn get_ignore_result(&self) -> bool {
+        self.ignore_result || T::ignore_result()
+    }
+    pub fn get_eta(&self) -> Option<SystemTime> {
+        self.eta
+    }
+    pub fn get_expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+    pub fn get_queue(&self) -> Option<String> {
+        self.queue.clone()
+    }
+    pub fn get_priority(&self) -> u8 {
+        self.priority
+    }
+    pub fn get_time_limit(&self) -> Option<Duration> {
+        self.time_limit.or_else(T::time_limit)
+    }
+    pub fn get_soft_time_limit(&self) -> Option<Duration> {
+        self.soft_time_limit.or_else(T::soft_time_limit)
+    }
     pub fn name(&self) -> &'static str {
         T::NAME
     }
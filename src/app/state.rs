@@ -0,0 +1,76 @@
+use std::any::{self, Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::error::TracerError;
+
+/// A value shared by every task, e.g. a database pool or a client,
+/// handed to `#[task]` functions taking a `State<T>` parameter.
+///
+/// Values are registered with `AsyncQueue::manage`.
+pub struct State<T>(Arc<T>);
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for State<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The shared values of an `AsyncQueue`, one per type.
+#[derive(Clone, Default)]
+pub struct StateMap {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl StateMap {
+    /// Add `value`, replacing the one of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// The value of type `T`, fails with `TracerError::StateNotFound` if none was added.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Result<State<T>, TracerError> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.clone().downcast::<T>().ok())
+            .map(State)
+            .ok_or_else(|| TracerError::StateNotFound(any::type_name::<T>().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_map() {
+        let mut state = StateMap::default();
+        state.insert(1u32);
+        state.insert("db".to_string());
+        assert_eq!(1, *state.get::<u32>().unwrap());
+        assert_eq!("db", state.get::<String>().unwrap().as_str());
+        assert!(matches!(
+            state.get::<u64>(),
+            Err(TracerError::StateNotFound(name)) if name == "u64"
+        ));
+
+        state.insert(2u32);
+        assert_eq!(2, *state.clone().get::<u32>().unwrap());
+    }
+}
@@ -1,14 +1,120 @@
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::panic::resume_unwind;
+use std::time::Duration;
 
-use crate::error::TaskError;
+use super::context::TaskContext;
+use super::retry::RetryPolicy;
+use super::state::StateMap;
+use crate::error::{TaskError, TracerError};
 
 pub type TaskReturn<R> = Result<R, TaskError>;
 
+#[async_trait]
 pub trait AQTask: Send + Sync {
     const NAME: &'static str;
     type Params: Send + Sync + Clone + Serialize + for<'de> Deserialize<'de>;
     type Returns: Send + Sync + Clone + Serialize + for<'de> Deserialize<'de> + fmt::Debug;
-    fn run(&self) -> Self::Returns;
-    fn from_params(params: Self::Params) -> Self;
+    /// Run the task, `ctx` tells it about itself and whether it should stop.
+    async fn run(&self, ctx: &TaskContext) -> TaskReturn<Self::Returns>;
+    /// Build the task from its params and the shared state of the `AsyncQueue`.
+    fn from_params(params: Self::Params, state: &StateMap) -> Result<Self, TracerError>
+    where
+        Self: Sized;
+
+    /// How failures of this task are retried,
+    /// unless overridden by `Signature::retry_policy`.
+    fn retry_policy() -> RetryPolicy
+    where
+        Self: Sized,
+    {
+        RetryPolicy::default()
+    }
+
+    /// Whether `err` is worth another attempt. Defaults to retry every error.
+    fn retry_on(_err: &TaskError) -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+
+    /// How long the result is kept, defaults to the one of the `AsyncQueue`.
+    fn result_ttl() -> Option<Duration>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Whether the result is never stored, for fire-and-forget tasks.
+    fn ignore_result() -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
+
+    /// The queue the task is sent to when neither the signature
+    /// nor a routing rule picks one, defaults to the one of the `AsyncQueue`.
+    fn queue() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// How long the task may run before it is aborted
+    /// and fails with `TaskError::TimeLimitExceeded`.
+    fn time_limit() -> Option<Duration>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// How long the task may run before it is told to wrap up
+    /// through `TaskContext::soft_time_limit_exceeded`.
+    fn soft_time_limit() -> Option<Duration>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
+
+/// Run a synchronous task body on the blocking thread pool,
+/// so that CPU heavy tasks do not stall the scheduler and other workers.
+/// A panic in `f` is resumed in the caller.
+pub async fn run_blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => resume_unwind(panic),
+            Err(e) => panic!("blocking task failed: {}", e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_blocking() {
+        let runtime_thread = std::thread::current().id();
+        let res = run_blocking(move || std::thread::current().id() != runtime_thread).await;
+        assert!(res);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "boom")]
+    async fn test_run_blocking_panic() {
+        run_blocking(|| panic!("boom")).await
+    }
 }
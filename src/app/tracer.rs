@@ -1,13 +1,39 @@
-use crate::error::TracerError;
+use crate::error::{TaskError, TracerError};
 
-use super::{message::Message, task::AQTask};
+use super::{
+    context::TaskContext, message::Message, retry::RetryPolicy, state::StateMap, task::AQTask,
+};
 use async_trait::async_trait;
+use futures::FutureExt;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
 
 #[async_trait]
 pub trait TracerTrait: Send + Sync {
     /// Wraps the execution of a task, catching and logging errors and then running
     /// the appropriate post-execution functions.
-    async fn run(&mut self) -> Result<String, TracerError>;
+    /// A panic in the task is caught and reported as `TaskError::Panicked`,
+    /// a return value that cannot be serialized as `TaskError::Unserializable`.
+    async fn run(&mut self, ctx: &TaskContext) -> Result<serde_json::Value, TaskError>;
+
+    /// The retry policy declared on the task.
+    fn retry_policy(&self) -> RetryPolicy;
+
+    /// Whether the task should be attempted again after failing with `err`.
+    fn should_retry(&self, err: &TaskError) -> bool;
+
+    /// How long the result is kept, if the task overrides it.
+    fn result_ttl(&self) -> Option<Duration>;
+
+    /// Whether the task never stores its result.
+    fn ignore_result(&self) -> bool;
+
+    /// The hard time limit declared on the task.
+    fn time_limit(&self) -> Option<Duration>;
+
+    /// The soft time limit declared on the task.
+    fn soft_time_limit(&self) -> Option<Duration>;
 }
 
 pub struct Tracer<T>
@@ -31,19 +57,64 @@ impl<T> TracerTrait for Tracer<T>
 where
     T: AQTask,
 {
-    async fn run(&mut self) -> Result<String, TracerError> {
-        let res = self.task.run();
-        serde_json::to_string(&res).map_err(|e| e.into())
+    async fn run(&mut self, ctx: &TaskContext) -> Result<serde_json::Value, TaskError> {
+        let res = match AssertUnwindSafe(self.task.run(ctx)).catch_unwind().await {
+            Ok(res) => res?,
+            Err(panic) => return Err(TaskError::Panicked(panic_message(&panic))),
+        };
+        serde_json::to_value(&res).map_err(|e| TaskError::Unserializable(e.to_string()))
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        T::retry_policy()
+    }
+
+    fn should_retry(&self, err: &TaskError) -> bool {
+        match err {
+            // the task would return the same value again.
+            TaskError::Unserializable(_) => false,
+            e => T::retry_on(e),
+        }
+    }
+
+    fn result_ttl(&self) -> Option<Duration> {
+        T::result_ttl()
+    }
+
+    fn ignore_result(&self) -> bool {
+        T::ignore_result()
+    }
+
+    fn time_limit(&self) -> Option<Duration> {
+        T::time_limit()
+    }
+
+    fn soft_time_limit(&self) -> Option<Duration> {
+        T::soft_time_limit()
+    }
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
     }
 }
 
 pub type TraceBuilderResult = Result<Box<dyn TracerTrait>, TracerError>;
 
-pub type TraceBuilder = Box<dyn Fn(Message) -> TraceBuilderResult + Send + Sync + 'static>;
+pub type TraceBuilder =
+    Box<dyn Fn(Message, &StateMap) -> TraceBuilderResult + Send + Sync + 'static>;
 
-pub fn build_trace<T: AQTask + Send + Sync + 'static>(msg: Message) -> TraceBuilderResult {
+pub fn build_trace<T: AQTask + Send + Sync + 'static>(
+    msg: Message,
+    state: &StateMap,
+) -> TraceBuilderResult {
     let payload = msg.get_payload();
     let params: T::Params = serde_json::from_slice(payload)?;
-    let task: T = T::from_params(params);
+    let task: T = T::from_params(params, state)?;
     Ok(Box::new(Tracer::<T>::new(task)))
 }
@@ -1,38 +1,86 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use tokio::sync::mpsc;
-use tokio::time::sleep;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 
+use crate::app::context::{Progress, TaskContext};
+use crate::app::dead_letter::DeadLetter;
 use crate::app::message::Message;
+use crate::app::record::{Retention, TaskRecord, TaskState};
+use crate::app::revoke::{self, Revocation, REVOKE_POLL_INTERVAL};
 use crate::broker::Broker;
-use crate::error::WorkerError;
+use crate::error::{TaskError, WorkerError};
 
 use super::AsyncQueue;
 
+/// The shortest interval between two extensions of a reservation.
+const MIN_EXTEND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A reserved task on its way from the scheduler to a worker.
+#[derive(Clone)]
+pub(crate) struct Delivery {
+    pub queue: String,
+    pub body: String,
+}
+
+impl Delivery {
+    /// The id of the task, or the body itself if it is not a message.
+    pub fn task_id(&self) -> String {
+        match serde_json::from_str::<Message>(&self.body) {
+            Ok(msg) => msg.get_id(),
+            Err(_) => self.body.clone(),
+        }
+    }
+}
+
+/// The deliveries being handled, by worker id.
+pub(crate) type InFlight = Arc<Mutex<HashMap<i32, Delivery>>>;
+
+/// What to do with a delivery once the worker is done with it.
+enum Outcome {
+    Ack,
+    /// keep the reservation, the task comes back once its visibility timeout expired
+    Keep,
+    Retry(Message, Duration),
+    /// give up on the message, for the given reason
+    DeadLetter(String),
+}
+
 pub(crate) struct Worker {
     id: i32,
-    broker: Box<dyn Broker>,
+    consumer: String,
+    visibility: Duration,
+    broker: Arc<dyn Broker>,
     app: Arc<AsyncQueue>,
+    in_flight: InFlight,
 }
 
 impl Worker {
-    pub fn new(i: i32, broker: Box<dyn Broker>, app: Arc<AsyncQueue>) -> Self {
+    pub fn new(
+        i: i32,
+        consumer: String,
+        visibility: Duration,
+        broker: Box<dyn Broker>,
+        app: Arc<AsyncQueue>,
+        in_flight: InFlight,
+    ) -> Self {
         Worker {
             id: i,
-            broker: broker,
-            app: app,
+            consumer,
+            visibility,
+            broker: Arc::from(broker),
+            app,
+            in_flight,
         }
     }
 
-    pub async fn start(
-        &self,
-        rx: async_channel::Receiver<String>,
-        tx: mpsc::Sender<()>,
-        _: mpsc::Sender<()>,
-    ) {
+    pub async fn start(&self, rx: async_channel::Receiver<Delivery>, tx: mpsc::Sender<()>) {
         let idx = self.id;
         info!(worker = idx, "start");
         loop {
@@ -40,16 +88,66 @@ impl Worker {
                 break;
             }
             if let Err(e) = tx.send(()).await {
+                if rx.is_closed() {
+                    break;
+                }
                 error!(worker = idx, "fail to give out token, {}", e.to_string());
-                let _ = sleep(Duration::from_secs(1));
+                sleep(Duration::from_secs(1)).await;
                 continue;
             }
             let res = rx.recv().await;
             match res {
-                Ok(val) => {
-                    info!(worker = idx, "got {}", val);
-                    if let Err(e) = self.handle(val).await {
-                        error!(worker = idx, "got error handle task {}", e);
+                Ok(delivery) => {
+                    info!(worker = idx, "got {}", delivery.body);
+                    self.in_flight.lock().unwrap().insert(idx, delivery.clone());
+                    let keeper = self.keep_reserved(&delivery);
+                    let handled = self.handle(&delivery).await;
+                    keeper.abort();
+                    let outcome = match handled {
+                        Ok(outcome) => outcome,
+                        Err(e) if e.is_poison() => Outcome::DeadLetter(e.to_string()),
+                        // the record may not be stored, e.g. the broker is unreachable,
+                        // so the task is not done with yet.
+                        Err(e) => {
+                            error!(worker = idx, "got error handle task {}", e);
+                            Outcome::Keep
+                        }
+                    };
+                    let done = match outcome {
+                        Outcome::Ack => true,
+                        Outcome::Keep => false,
+                        Outcome::Retry(msg, delay) => match self.retry(&delivery, msg, delay).await
+                        {
+                            Ok(_) => true,
+                            // keep the reservation, the task comes back
+                            // once its visibility timeout expired.
+                            Err(e) => {
+                                error!(worker = idx, "fail to retry task, {}", e);
+                                false
+                            }
+                        },
+                        Outcome::DeadLetter(reason) => {
+                            match self.dead_letter(&delivery, reason).await {
+                                Ok(_) => true,
+                                Err(e) => {
+                                    error!(worker = idx, "fail to dead-letter task, {}", e);
+                                    false
+                                }
+                            }
+                        }
+                    };
+                    // from now on the task must not be given back on shutdown.
+                    self.in_flight.lock().unwrap().remove(&idx);
+                    // the task is done with, successfully or not,
+                    // so it must not be handed out again.
+                    if done {
+                        if let Err(e) = self
+                            .broker
+                            .ack(&delivery.queue, &self.consumer, &delivery.body)
+                            .await
+                        {
+                            error!(worker = idx, "fail to ack task, {}", e);
+                        }
                     }
                 }
                 Err(e) => {
@@ -63,24 +161,213 @@ impl Worker {
         info!(worker = idx, "stopped");
     }
 
-    async fn handle(&self, val: String) -> Result<(), WorkerError> {
+    async fn handle(&self, delivery: &Delivery) -> Result<Outcome, WorkerError> {
         let idx = self.id;
 
-        let msg: Message = serde_json::from_str(&val[..])?;
+        let msg: Message = serde_json::from_str(&delivery.body)?;
         let id = msg.get_id();
         let name = msg.get_name();
 
         let payload = String::from_utf8_lossy(msg.get_payload());
         info!(worker = idx, "got task {}, {}", id, payload);
 
-        let result = self.handle_message(name, msg).await?;
-        self.broker.set(&id, &result).await?;
-        info!(worker = idx, "write result to {}, {}", id, result);
+        // an expired task is dropped even if it cannot be run here,
+        // so only fail on the tracer once expiry is checked.
+        let tracer = self.app.get_tracer(name, msg.clone()).await;
+        let task = tracer.as_ref().ok();
+        let retention = Retention {
+            ttl: self.app.result_ttl(task.and_then(|t| t.result_ttl())).await,
+            ignore: msg.get_ignore_result() || task.is_some_and(|t| t.ignore_result()),
+        };
+        if msg.is_expired() {
+            warn!(worker = idx, "task {} expired, drop it", id);
+            let mut record = TaskRecord::new(TaskState::Pending);
+            record.expire();
+            record
+                .save::<WorkerError>(self.broker.as_ref(), &id, retention)
+                .await?;
+            return Ok(Outcome::Ack);
+        }
+        let mut tracer = tracer?;
+
+        if self.drop_revoked(&id, retention).await? {
+            return Ok(Outcome::Ack);
+        }
+
+        let record = TaskRecord::started(self.worker_id(), msg.get_retries());
+        record
+            .save::<WorkerError>(self.broker.as_ref(), &id, retention)
+            .await?;
+        // a client revoking the task in between saw it pending and wrote
+        // its record, which was just overwritten, so look again.
+        if self.drop_revoked(&id, retention).await? {
+            return Ok(Outcome::Ack);
+        }
+
+        let time_limit = msg.get_time_limit().or_else(|| tracer.time_limit());
+        let deadline = time_limit.map(|limit| SystemTime::now() + limit);
+        let progress = Progress::new(id.clone(), self.broker.clone(), retention, record);
+        // messages of older clients do not know their queue, only the broker one.
+        let queue = msg.get_queue().unwrap_or_else(|| delivery.queue.clone());
+        let ctx = TaskContext::new(&msg, queue, deadline).report_to(progress.clone());
+        let watcher = self.watch_revocation(&ctx);
+        let soft_timer = msg
+            .get_soft_time_limit()
+            .or_else(|| tracer.soft_time_limit())
+            .map(|limit| {
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    sleep(limit).await;
+                    ctx.exceed_soft_time_limit();
+                })
+            });
+        // a sync task keeps its blocking thread when aborted,
+        // but its worker is free to go on.
+        let res = match time_limit {
+            Some(limit) => match timeout(limit, tracer.run(&ctx)).await {
+                Ok(res) => res,
+                Err(_) => Err(TaskError::TimeLimitExceeded(limit)),
+            },
+            None => tracer.run(&ctx).await,
+        };
+        watcher.abort();
+        if let Some(soft_timer) = soft_timer {
+            soft_timer.abort();
+        }
+        let mut record = progress
+            .finish()
+            .await
+            .unwrap_or_else(|| TaskRecord::started(self.worker_id(), msg.get_retries()));
+        if ctx.is_cancelled() {
+            warn!(worker = idx, "task {} terminated", id);
+            record.revoke();
+            record
+                .save::<WorkerError>(self.broker.as_ref(), &id, retention)
+                .await?;
+            return Ok(Outcome::Ack);
+        }
+
+        match res {
+            Ok(result) => record.succeed(result),
+            Err(e) => {
+                let policy = msg
+                    .get_retry_policy()
+                    .unwrap_or_else(|| tracer.retry_policy());
+                let retriable = tracer.should_retry(&e);
+                if retriable {
+                    if let Some(delay) = policy.next_delay(msg.get_retries()) {
+                        warn!(
+                            worker = idx,
+                            "task {} failed, retry in {:?}, {}", id, delay, e
+                        );
+                        record.retry(e);
+                        record
+                            .save::<WorkerError>(self.broker.as_ref(), &id, retention)
+                            .await?;
+                        return Ok(Outcome::Retry(msg.retry(), delay));
+                    }
+                }
+                let reason = format!("exhausted {} retries, {}", policy.max_retries, e);
+                error!(worker = idx, "task {} failed, {}", id, e);
+                record.fail(e);
+                if retriable && policy.max_retries > 0 {
+                    record
+                        .save::<WorkerError>(self.broker.as_ref(), &id, retention)
+                        .await?;
+                    return Ok(Outcome::DeadLetter(reason));
+                }
+            }
+        };
+        record
+            .save::<WorkerError>(self.broker.as_ref(), &id, retention)
+            .await?;
+        info!(worker = idx, "write result to {}, {:?}", id, record.state);
+        Ok(Outcome::Ack)
+    }
+
+    /// Whether the task `id` is revoked, its record is then written as such.
+    async fn drop_revoked(&self, id: &str, retention: Retention) -> Result<bool, WorkerError> {
+        if revoke::get(self.broker.as_ref(), id).await?.is_none() {
+            return Ok(false);
+        }
+        warn!(worker = self.id, "task {} revoked, drop it", id);
+        let mut record = TaskRecord::new(TaskState::Pending);
+        record.revoke();
+        record
+            .save::<WorkerError>(self.broker.as_ref(), id, retention)
+            .await?;
+        Ok(true)
+    }
+
+    fn worker_id(&self) -> String {
+        format!("{}:{}", self.consumer, self.id)
+    }
+
+    /// Spawn a loop cancelling `ctx` once its task is revoked with `terminate`.
+    fn watch_revocation(&self, ctx: &TaskContext) -> JoinHandle<()> {
+        let broker = self.broker.clone();
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(REVOKE_POLL_INTERVAL).await;
+                match revoke::get(broker.as_ref(), ctx.id()).await {
+                    Ok(Some(Revocation::Terminate)) => return ctx.cancel(),
+                    Ok(_) => {}
+                    Err(e) => error!("fail to check revocation of {}, {}", ctx.id(), e),
+                }
+            }
+        })
+    }
+
+    /// Spawn a loop extending the reservation of `delivery` while it is handled,
+    /// so that a task running past the visibility timeout is not handed out twice.
+    fn keep_reserved(&self, delivery: &Delivery) -> JoinHandle<()> {
+        let broker = self.broker.clone();
+        let consumer = self.consumer.clone();
+        let delivery = delivery.clone();
+        let visibility = self.visibility;
+        tokio::spawn(async move {
+            loop {
+                sleep((visibility / 2).max(MIN_EXTEND_INTERVAL)).await;
+                if let Err(e) = broker
+                    .extend(&delivery.queue, &consumer, &delivery.body, visibility)
+                    .await
+                {
+                    error!(
+                        "fail to extend reservation of {}, {}",
+                        delivery.task_id(),
+                        e
+                    );
+                }
+            }
+        })
+    }
+
+    /// Keep a delivery no worker can handle in the dead-letter queue, if any.
+    async fn dead_letter(&self, delivery: &Delivery, reason: String) -> Result<(), WorkerError> {
+        error!(
+            worker = self.id,
+            "give up on task from {}, {}", delivery.queue, reason
+        );
+        let queue = match self.app.dead_letter_queue().await {
+            Some(queue) => queue,
+            None => return Ok(()),
+        };
+        let letter = DeadLetter::new(delivery.body.clone(), delivery.queue.clone(), reason);
+        self.broker.enqueue(&queue, &letter.serialize()?).await?;
         Ok(())
     }
 
-    async fn handle_message(&self, name: String, msg: Message) -> Result<String, WorkerError> {
-        let mut tracer = self.app.get_tracer(name, msg).await?;
-        tracer.run().await.map_err(|e| e.into())
+    /// Schedule the next attempt of a task once `delay` elapsed.
+    async fn retry(
+        &self,
+        delivery: &Delivery,
+        msg: Message,
+        delay: Duration,
+    ) -> Result<(), WorkerError> {
+        let val = msg.serialize()?;
+        let at = SystemTime::now() + delay;
+        self.broker.enqueue_at(&delivery.queue, &val, at).await?;
+        Ok(())
     }
 }
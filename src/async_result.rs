@@ -1,19 +1,41 @@
+use std::future::{Future, IntoFuture};
 use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
+use serde::de::DeserializeOwned;
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::app::record::{TaskRecord, TaskState};
+use crate::app::task::TaskReturn;
 use crate::app::{signature::Signature, task::AQTask};
+use crate::broker::Broker;
+use crate::error::{ClientError, MsgError};
+
+/// How long to wait for a completion notification before
+/// checking the result again. Brokers without notifications poll at this rate.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
 
+/// A handle on the result of a submitted task.
+///
+/// Awaiting it waits for the task to finish without a time limit,
+/// use `get` to give up after a while.
 pub struct AsyncResult<T>
 where
     T: AQTask,
 {
     id: String,
+    broker: Arc<dyn Broker>,
     phantom: PhantomData<T>,
 }
 
 impl<T: AQTask> AsyncResult<T> {
-    pub fn new(sig: &Signature<T>) -> AsyncResult<T> {
+    pub fn new(sig: &Signature<T>, broker: Arc<dyn Broker>) -> AsyncResult<T> {
         AsyncResult {
             id: sig.get_id(),
+            broker,
             phantom: PhantomData,
         }
     }
@@ -21,4 +43,66 @@ impl<T: AQTask> AsyncResult<T> {
     pub fn get_id(&self) -> String {
         self.id.clone()
     }
+
+    /// Wait at most `to` for the task to finish and return its result.
+    pub async fn get(&self, to: Duration) -> Result<TaskReturn<T::Returns>, ClientError> {
+        let record = timeout(to, self.wait()).await??;
+        Ok(record.into_return())
+    }
+
+    /// The current state of the task.
+    pub async fn state(&self) -> Result<TaskState, ClientError> {
+        match self.record().await? {
+            Some(record) => Ok(record.state),
+            None => Ok(TaskState::Pending),
+        }
+    }
+
+    /// The last progress reported by the task through `TaskContext::report_progress`.
+    pub async fn progress<P: DeserializeOwned>(&self) -> Result<Option<P>, ClientError> {
+        match self.record().await?.and_then(|record| record.progress) {
+            Some(progress) => Ok(Some(
+                serde_json::from_value(progress).map_err(MsgError::from)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the task finished, successfully or not.
+    pub async fn ready(&self) -> Result<bool, ClientError> {
+        Ok(self.state().await?.is_ready())
+    }
+
+    /// Remove the result of the task from the broker.
+    pub async fn forget(self) -> Result<(), ClientError> {
+        self.broker.delete(&self.id).await.map_err(|e| e.into())
+    }
+
+    async fn record(&self) -> Result<Option<TaskRecord>, ClientError> {
+        match self.broker.get(&self.id).await? {
+            Some(res) => Ok(Some(TaskRecord::deserialize(&res)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn wait(&self) -> Result<TaskRecord, ClientError> {
+        loop {
+            debug!("start polling");
+            if let Some(record) = self.record().await? {
+                if record.state.is_ready() {
+                    return Ok(record);
+                }
+            }
+            self.broker.wait_notified(&self.id, POLL_INTERVAL).await?;
+        }
+    }
+}
+
+impl<T: AQTask + 'static> IntoFuture for AsyncResult<T> {
+    type Output = Result<TaskReturn<T::Returns>, ClientError>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move { Ok(self.wait().await?.into_return()) })
+    }
 }
@@ -0,0 +1,594 @@
+use super::{Broker, BrokerBuilder};
+use crate::error::BrokerError;
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// A value handed out by `reserve` and not acknowledged yet.
+struct Unacked {
+    consumer: String,
+    val: String,
+    deadline: Instant,
+}
+
+/// A value that disappears once `expire_at` passed.
+struct Expiring<V> {
+    val: V,
+    expire_at: Option<Instant>,
+}
+
+impl<V> Expiring<V> {
+    fn new(val: V, ttl: Option<Duration>) -> Self {
+        Expiring {
+            val,
+            expire_at: ttl.map(|ttl| Instant::now() + ttl),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.expire_at.is_none_or(|at| at > Instant::now())
+    }
+}
+
+/// Look `key` up in `map`, dropping it if it expired.
+fn get_alive<'a, V>(map: &'a mut HashMap<String, Expiring<V>>, key: &str) -> Option<&'a V> {
+    if !map.get(key)?.is_alive() {
+        map.remove(key);
+        return None;
+    }
+    map.get(key).map(|e| &e.val)
+}
+
+/// State shared by every `MemoryBroker` built from the same builder.
+#[derive(Default)]
+struct MemoryStore {
+    values: Mutex<HashMap<String, Expiring<String>>>,
+    queues: Mutex<HashMap<String, VecDeque<String>>>,
+    delayed: Mutex<HashMap<String, Vec<(SystemTime, String)>>>,
+    unacked: Mutex<HashMap<String, Vec<Unacked>>>,
+    notified: Mutex<HashMap<String, Expiring<()>>>,
+    // woken up on every enqueue and notify so that blocked callers can retry.
+    notify: Notify,
+}
+
+impl MemoryStore {
+    /// Pop from the first of `queues` that has a value.
+    fn pop(&self, queues: &[String]) -> Option<(String, String)> {
+        let mut store = self.queues.lock().unwrap();
+        queues.iter().find_map(|queue| {
+            let val = store.get_mut(queue)?.pop_front()?;
+            Some((queue.clone(), val))
+        })
+    }
+
+    fn is_notified(&self, key: &str) -> bool {
+        let mut notified = self.notified.lock().unwrap();
+        get_alive(&mut notified, key).is_some()
+    }
+
+    /// Move the delayed values of `queue` due at `now` onto it, returns how many were moved.
+    fn promote(&self, queue: &str, now: SystemTime) -> usize {
+        let mut due: Vec<(SystemTime, String)> = {
+            let mut delayed = self.delayed.lock().unwrap();
+            match delayed.get_mut(queue) {
+                Some(list) => {
+                    let (due, later) = list.drain(..).partition(|(at, _)| *at <= now);
+                    *list = later;
+                    due
+                }
+                None => vec![],
+            }
+        };
+        due.sort_by_key(|(at, _)| *at);
+        let mut queues = self.queues.lock().unwrap();
+        let q = queues.entry(queue.to_string()).or_default();
+        q.extend(due.iter().map(|(_, val)| val.clone()));
+        due.len()
+    }
+
+    /// Give the values of `queue` reserved past their deadline back to it,
+    /// returns how many were requeued.
+    fn requeue(&self, queue: &str, now: Instant) -> usize {
+        let expired: Vec<String> = {
+            let mut unacked = self.unacked.lock().unwrap();
+            match unacked.get_mut(queue) {
+                Some(list) => {
+                    let (expired, alive) = list.drain(..).partition(|u| u.deadline <= now);
+                    *list = alive;
+                    expired.into_iter().map(|u: Unacked| u.val).collect()
+                }
+                None => vec![],
+            }
+        };
+        let mut queues = self.queues.lock().unwrap();
+        let q = queues.entry(queue.to_string()).or_default();
+        for val in expired.iter().rev() {
+            q.push_front(val.clone());
+        }
+        expired.len()
+    }
+
+    async fn wait_pop(&self, queues: &[String]) -> (String, String) {
+        loop {
+            // register interest before checking the queue,
+            // otherwise an enqueue in between would be missed.
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(popped) = self.pop(queues) {
+                return popped;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Builds brokers that keep everything inside the current process.
+///
+/// All brokers built from one `MemoryBrokerBuilder` share the same storage,
+/// so a `Client` and a `Server` of the same `AsyncQueue` can talk to each other.
+pub struct MemoryBrokerBuilder {
+    _url: String,
+    store: Arc<MemoryStore>,
+}
+
+#[async_trait]
+impl BrokerBuilder for MemoryBrokerBuilder {
+    fn new(broker_url: String) -> Result<Self, BrokerError>
+    where
+        Self: Sized,
+    {
+        Ok(MemoryBrokerBuilder {
+            _url: broker_url,
+            store: Arc::new(MemoryStore::default()),
+        })
+    }
+
+    async fn build(&self, _timeout: u32) -> Result<Box<dyn Broker>, BrokerError> {
+        Ok(Box::new(MemoryBroker {
+            store: self.store.clone(),
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryBroker {
+    store: Arc<MemoryStore>,
+}
+
+#[async_trait]
+impl Broker for MemoryBroker {
+    async fn get(&self, key: &str) -> Result<Option<String>, BrokerError> {
+        let mut values = self.store.values.lock().unwrap();
+        Ok(get_alive(&mut values, key).cloned())
+    }
+
+    async fn set(&self, key: &str, val: &str, ttl: Option<Duration>) -> Result<(), BrokerError> {
+        let mut values = self.store.values.lock().unwrap();
+        values.insert(key.to_string(), Expiring::new(val.to_string(), ttl));
+        Ok(())
+    }
+
+    async fn set_nx(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, BrokerError> {
+        let mut values = self.store.values.lock().unwrap();
+        if get_alive(&mut values, key).is_some() {
+            return Ok(false);
+        }
+        values.insert(key.to_string(), Expiring::new(val.to_string(), ttl));
+        Ok(true)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BrokerError> {
+        self.store.values.lock().unwrap().remove(key);
+        self.store.notified.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn enqueue(&self, queue: &str, val: &str) -> Result<(), BrokerError> {
+        {
+            let mut queues = self.store.queues.lock().unwrap();
+            queues
+                .entry(queue.to_string())
+                .or_default()
+                .push_back(val.to_string());
+        }
+        self.store.notify.notify_waiters();
+        Ok(())
+    }
+
+    async fn enqueue_at(&self, queue: &str, val: &str, at: SystemTime) -> Result<(), BrokerError> {
+        let mut delayed = self.store.delayed.lock().unwrap();
+        delayed
+            .entry(queue.to_string())
+            .or_default()
+            .push((at, val.to_string()));
+        Ok(())
+    }
+
+    async fn promote_due(&self, queues: &[String]) -> Result<usize, BrokerError> {
+        let mut count = 0;
+        for queue in queues {
+            count += self.store.promote(queue, SystemTime::now());
+        }
+        if count > 0 {
+            self.store.notify.notify_waiters();
+        }
+        Ok(count)
+    }
+
+    async fn dequeue(&self, queue: &str) -> Result<Option<(String, String)>, BrokerError> {
+        let popped = self.store.wait_pop(&[queue.to_string()]).await;
+        Ok(Some(popped))
+    }
+
+    async fn peek(
+        &self,
+        queue: &str,
+        start: usize,
+        count: usize,
+    ) -> Result<Vec<String>, BrokerError> {
+        let queues = self.store.queues.lock().unwrap();
+        let vals = match queues.get(queue) {
+            Some(q) => q.iter().skip(start).take(count).cloned().collect(),
+            None => Vec::new(),
+        };
+        Ok(vals)
+    }
+
+    async fn remove(&self, queue: &str, val: &str) -> Result<usize, BrokerError> {
+        let mut queues = self.store.queues.lock().unwrap();
+        let q = match queues.get_mut(queue) {
+            Some(q) => q,
+            None => return Ok(0),
+        };
+        let len = q.len();
+        q.retain(|v| v != val);
+        Ok(len - q.len())
+    }
+
+    async fn purge(&self, queue: &str) -> Result<usize, BrokerError> {
+        let mut queues = self.store.queues.lock().unwrap();
+        Ok(queues.remove(queue).map_or(0, |q| q.len()))
+    }
+
+    async fn reserve(
+        &self,
+        queues: &[String],
+        consumer: &str,
+        visibility: Duration,
+        wait: Duration,
+    ) -> Result<Option<(String, String)>, BrokerError> {
+        // popping and recording happen without an await in between,
+        // so a cancelled reserve never loses a value.
+        let (queue, val) = match tokio::time::timeout(wait, self.store.wait_pop(queues)).await {
+            Ok(popped) => popped,
+            Err(_) => return Ok(None),
+        };
+        let mut unacked = self.store.unacked.lock().unwrap();
+        unacked.entry(queue.clone()).or_default().push(Unacked {
+            consumer: consumer.to_string(),
+            val: val.clone(),
+            deadline: Instant::now() + visibility,
+        });
+        Ok(Some((queue, val)))
+    }
+
+    async fn extend(
+        &self,
+        queue: &str,
+        consumer: &str,
+        val: &str,
+        visibility: Duration,
+    ) -> Result<(), BrokerError> {
+        let mut unacked = self.store.unacked.lock().unwrap();
+        if let Some(reserved) = unacked.get_mut(queue).and_then(|list| {
+            list.iter_mut()
+                .find(|u| u.consumer == consumer && u.val == val)
+        }) {
+            reserved.deadline = Instant::now() + visibility;
+        }
+        Ok(())
+    }
+
+    async fn ack(&self, queue: &str, consumer: &str, val: &str) -> Result<(), BrokerError> {
+        let mut unacked = self.store.unacked.lock().unwrap();
+        if let Some(list) = unacked.get_mut(queue) {
+            if let Some(pos) = list
+                .iter()
+                .position(|u| u.consumer == consumer && u.val == val)
+            {
+                list.remove(pos);
+            }
+        }
+        Ok(())
+    }
+
+    async fn release(&self, queue: &str, consumer: &str, val: &str) -> Result<(), BrokerError> {
+        {
+            let mut unacked = self.store.unacked.lock().unwrap();
+            let Some(list) = unacked.get_mut(queue) else {
+                return Ok(());
+            };
+            // a value requeued since is not ours to give back anymore.
+            let Some(pos) = list
+                .iter()
+                .position(|u| u.consumer == consumer && u.val == val)
+            else {
+                return Ok(());
+            };
+            list.remove(pos);
+            let mut queues = self.store.queues.lock().unwrap();
+            queues
+                .entry(queue.to_string())
+                .or_default()
+                .push_front(val.to_string());
+        }
+        self.store.notify.notify_waiters();
+        Ok(())
+    }
+
+    async fn requeue_expired(&self, queues: &[String]) -> Result<usize, BrokerError> {
+        let now = Instant::now();
+        let mut count = 0;
+        for queue in queues {
+            count += self.store.requeue(queue, now);
+        }
+        if count > 0 {
+            self.store.notify.notify_waiters();
+        }
+        Ok(count)
+    }
+
+    async fn notify(&self, key: &str, ttl: Option<Duration>) -> Result<(), BrokerError> {
+        {
+            let mut notified = self.store.notified.lock().unwrap();
+            notified.insert(key.to_string(), Expiring::new((), ttl));
+        }
+        self.store.notify.notify_waiters();
+        Ok(())
+    }
+
+    async fn wait_notified(&self, key: &str, timeout: Duration) -> Result<(), BrokerError> {
+        let wait = async {
+            loop {
+                let notified = self.store.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+
+                if self.store.is_notified(key) {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        let _ = tokio::time::timeout(timeout, wait).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+
+    async fn broker() -> Box<dyn Broker> {
+        MemoryBrokerBuilder::new("memory://".into())
+            .unwrap()
+            .build(10)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_set() {
+        let broker = broker().await;
+        assert_eq!(None, broker.get("key").await.unwrap());
+        broker.set("key", "val", None).await.unwrap();
+        assert_eq!(Some("val".to_string()), broker.get("key").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_set_ttl() {
+        let broker = broker().await;
+        let ttl = Some(Duration::from_millis(10));
+        broker.set("key", "val", ttl).await.unwrap();
+        assert_eq!(Some("val".to_string()), broker.get("key").await.unwrap());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(None, broker.get("key").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_set_nx() {
+        let broker = broker().await;
+        let ttl = Some(Duration::from_millis(10));
+        assert!(broker.set_nx("lock", "a", ttl).await.unwrap());
+        assert!(!broker.set_nx("lock", "b", ttl).await.unwrap());
+        assert_eq!(Some("a".to_string()), broker.get("lock").await.unwrap());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(broker.set_nx("lock", "b", ttl).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_queue_order() {
+        let broker = broker().await;
+        broker.enqueue("q", "1").await.unwrap();
+        broker.enqueue("q", "2").await.unwrap();
+        let first = broker.dequeue("q").await.unwrap();
+        let second = broker.dequeue("q").await.unwrap();
+        assert_eq!(Some(("q".to_string(), "1".to_string())), first);
+        assert_eq!(Some(("q".to_string(), "2".to_string())), second);
+    }
+
+    #[tokio::test]
+    async fn test_peek_remove_purge() {
+        let broker = broker().await;
+        for val in ["1", "2", "1", "3"] {
+            broker.enqueue("q", val).await.unwrap();
+        }
+        assert_eq!(vec!["2", "1"], broker.peek("q", 1, 2).await.unwrap());
+        assert_eq!(2, broker.remove("q", "1").await.unwrap());
+        assert_eq!(vec!["2", "3"], broker.peek("q", 0, 10).await.unwrap());
+        assert_eq!(2, broker.purge("q").await.unwrap());
+        assert_eq!(0, broker.purge("q").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_blocking_dequeue() {
+        let builder = MemoryBrokerBuilder::new("memory://".into()).unwrap();
+        let consumer = builder.build(10).await.unwrap();
+        let producer = builder.build(10).await.unwrap();
+
+        let h = tokio::spawn(async move { consumer.dequeue("q").await });
+        tokio::task::yield_now().await;
+        producer.enqueue("q", "val").await.unwrap();
+
+        let res = timeout(Duration::from_secs(1), h).await.unwrap().unwrap();
+        assert_eq!(Some(("q".to_string(), "val".to_string())), res.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reserve_ack() {
+        let broker = broker().await;
+        broker.enqueue("q", "1").await.unwrap();
+        broker.enqueue("q", "2").await.unwrap();
+
+        let visibility = Duration::from_millis(10);
+        let wait = Duration::from_secs(1);
+        let queues = ["q".to_string()];
+        let reserve = |wait| broker.reserve(&queues, "c", visibility, wait);
+        let (_, first) = reserve(Duration::ZERO).await.unwrap().unwrap();
+        let (_, second) = reserve(wait).await.unwrap().unwrap();
+        assert!(reserve(Duration::ZERO).await.unwrap().is_none());
+        broker.ack("q", "c", &first).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(1, broker.requeue_expired(&queues).await.unwrap());
+        assert_eq!(0, broker.requeue_expired(&queues).await.unwrap());
+
+        let again = broker.dequeue("q").await.unwrap();
+        assert_eq!(Some(("q".to_string(), second)), again);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_first_queue() {
+        let broker = broker().await;
+        broker.enqueue("low", "1").await.unwrap();
+        broker.enqueue("high", "2").await.unwrap();
+
+        let queues = ["high".to_string(), "low".to_string()];
+        let visibility = Duration::from_secs(10);
+        let reserve = || broker.reserve(&queues, "c", visibility, Duration::ZERO);
+        let first = reserve().await.unwrap();
+        let second = reserve().await.unwrap();
+        assert_eq!(Some(("high".to_string(), "2".to_string())), first);
+        assert_eq!(Some(("low".to_string(), "1".to_string())), second);
+        assert!(reserve().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_release() {
+        let broker = broker().await;
+        broker.enqueue("q", "1").await.unwrap();
+        broker.enqueue("q", "2").await.unwrap();
+
+        let visibility = Duration::from_millis(10);
+        let queues = ["q".to_string()];
+        let (_, first) = broker
+            .reserve(&queues, "c", visibility, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+        broker.release("q", "c", &first).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(0, broker.requeue_expired(&queues).await.unwrap());
+
+        let again = broker.dequeue("q").await.unwrap();
+        assert_eq!(Some(("q".to_string(), first)), again);
+    }
+
+    #[tokio::test]
+    async fn test_stale_consumer() {
+        let broker = broker().await;
+        broker.enqueue("q", "1").await.unwrap();
+
+        let queues = ["q".to_string()];
+        let visibility = Duration::from_millis(10);
+        broker
+            .reserve(&queues, "a", visibility, Duration::ZERO)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(1, broker.requeue_expired(&queues).await.unwrap());
+        let visibility = Duration::from_secs(10);
+        broker
+            .reserve(&queues, "b", visibility, Duration::ZERO)
+            .await
+            .unwrap();
+
+        // "a" gave up on the value, it cannot give it back nor extend it anymore
+        broker.release("q", "a", "1").await.unwrap();
+        broker.extend("q", "a", "1", visibility).await.unwrap();
+        assert!(broker.peek("q", 0, 10).await.unwrap().is_empty());
+        assert_eq!(0, broker.requeue_expired(&queues).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let builder = MemoryBrokerBuilder::new("memory://".into()).unwrap();
+        let waiter = builder.build(10).await.unwrap();
+        let notifier = builder.build(10).await.unwrap();
+
+        let h = tokio::spawn(async move {
+            waiter
+                .wait_notified("key", Duration::from_secs(10))
+                .await
+                .unwrap()
+        });
+        tokio::task::yield_now().await;
+        notifier.notify("key", None).await.unwrap();
+        timeout(Duration::from_secs(1), h).await.unwrap().unwrap();
+
+        // notifications stick for late waiters
+        let started = tokio::time::Instant::now();
+        notifier
+            .wait_notified("key", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_delayed() {
+        let broker = broker().await;
+        let now = SystemTime::now();
+        broker
+            .enqueue_at("q", "later", now + Duration::from_secs(60))
+            .await
+            .unwrap();
+        broker
+            .enqueue_at("q", "second", now - Duration::from_secs(1))
+            .await
+            .unwrap();
+        broker
+            .enqueue_at("q", "first", now - Duration::from_secs(2))
+            .await
+            .unwrap();
+
+        let queues = ["q".to_string()];
+        assert_eq!(2, broker.promote_due(&queues).await.unwrap());
+        assert_eq!(0, broker.promote_due(&queues).await.unwrap());
+        let first = broker.dequeue("q").await.unwrap();
+        let second = broker.dequeue("q").await.unwrap();
+        assert_eq!(Some(("q".to_string(), "first".to_string())), first);
+        assert_eq!(Some(("q".to_string(), "second".to_string())), second);
+    }
+}
@@ -1,13 +1,36 @@
+pub mod memory;
 pub mod redis;
 
+pub use self::memory::MemoryBrokerBuilder;
 pub use self::redis::RedisBrokerBuilder;
 use crate::error::BrokerError;
 use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+/// Pick a `BrokerBuilder` according to the scheme of `broker_url`.
+///
+/// Brokers that are not shipped with this crate can be plugged in
+/// through `AsyncQueue::with_broker` instead.
+pub fn builder_from_url(broker_url: String) -> Result<Arc<dyn BrokerBuilder>, BrokerError> {
+    let scheme = match broker_url.split_once("://") {
+        Some((scheme, _)) => scheme.to_string(),
+        None => return Err(BrokerError::UnsupportedScheme(broker_url)),
+    };
+    match &scheme[..] {
+        "redis" | "rediss" | "redis+unix" | "unix" => {
+            Ok(Arc::new(RedisBrokerBuilder::new(broker_url)?))
+        }
+        "memory" => Ok(Arc::new(MemoryBrokerBuilder::new(broker_url)?)),
+        _ => Err(BrokerError::UnsupportedScheme(scheme)),
+    }
+}
+
+/// Creates `Broker` connections for an `AsyncQueue`.
 #[async_trait]
 pub trait BrokerBuilder: Send + Sync {
-    /// Create a new `BrokerBuilder`.
-    fn new(broker_url: String) -> Self
+    /// Create a new `BrokerBuilder`, failing if `broker_url` is malformed.
+    fn new(broker_url: String) -> Result<Self, BrokerError>
     where
         Self: Sized;
 
@@ -15,10 +38,162 @@ pub trait BrokerBuilder: Send + Sync {
     async fn build(&self, timeout: u32) -> Result<Box<dyn Broker>, BrokerError>;
 }
 
+/// The storage and transport used by clients, servers and workers.
 #[async_trait]
 pub trait Broker: Send + Sync {
-    async fn get(&self, key: &String) -> Result<Option<String>, BrokerError>;
-    async fn set(&self, key: &String, val: &String) -> Result<(), BrokerError>;
-    async fn enqueue(&self, queue: &String, val: &String) -> Result<(), BrokerError>;
-    async fn dequeue(&self, queue: &String) -> Result<Option<(String, String)>, BrokerError>;
+    /// Read the value stored under `key`.
+    async fn get(&self, key: &str) -> Result<Option<String>, BrokerError>;
+    /// Store `val` under `key`, overwriting any previous value.
+    /// The value is removed after `ttl`, if given.
+    async fn set(&self, key: &str, val: &str, ttl: Option<Duration>) -> Result<(), BrokerError>;
+    /// Store `val` under `key` like `set`, unless `key` already holds a value.
+    /// Returns whether the value was stored, which makes it usable as a lock.
+    async fn set_nx(
+        &self,
+        _key: &str,
+        _val: &str,
+        _ttl: Option<Duration>,
+    ) -> Result<bool, BrokerError> {
+        Err(BrokerError::Unsupported("conditional set".into()))
+    }
+    /// Remove `key`, together with notifications sent for it.
+    async fn delete(&self, key: &str) -> Result<(), BrokerError>;
+
+    /// Push `val` to the tail of `queue`.
+    async fn enqueue(&self, queue: &str, val: &str) -> Result<(), BrokerError>;
+    /// Read up to `count` values of `queue` from `start` on, without popping them.
+    async fn peek(
+        &self,
+        _queue: &str,
+        _start: usize,
+        _count: usize,
+    ) -> Result<Vec<String>, BrokerError> {
+        Err(BrokerError::Unsupported("queue inspection".into()))
+    }
+    /// Remove every occurrence of `val` from `queue`, returns how many were removed.
+    async fn remove(&self, _queue: &str, _val: &str) -> Result<usize, BrokerError> {
+        Err(BrokerError::Unsupported("queue inspection".into()))
+    }
+    /// Remove every value of `queue`, returns how many were removed.
+    async fn purge(&self, _queue: &str) -> Result<usize, BrokerError> {
+        Err(BrokerError::Unsupported("queue inspection".into()))
+    }
+
+    /// Push `val` to the tail of `queue` once `at` is reached, see `promote_due`.
+    async fn enqueue_at(
+        &self,
+        _queue: &str,
+        _val: &str,
+        _at: SystemTime,
+    ) -> Result<(), BrokerError> {
+        Err(BrokerError::Unsupported("delayed enqueue".into()))
+    }
+
+    /// Move the values of every queue in `queues` whose time came onto the queue.
+    /// Returns how many values were moved.
+    async fn promote_due(&self, _queues: &[String]) -> Result<usize, BrokerError> {
+        Ok(0)
+    }
+
+    /// Pop from the head of `queue`, waiting until a value is available.
+    /// Returns the queue name together with the value.
+    async fn dequeue(&self, queue: &str) -> Result<Option<(String, String)>, BrokerError>;
+
+    /// Pop from the head of the first queue in `queues` that has a value, like `dequeue`,
+    /// but keep the value in a processing list owned by `consumer` until it is acknowledged.
+    /// Values not acknowledged within `visibility` are given back to their queue
+    /// by `requeue_expired`.
+    ///
+    /// Waits at most `wait` for a value, returning `None` if there is none;
+    /// a zero `wait` only checks whether a value is available.
+    ///
+    /// The default implementation is not reliable and simply calls `dequeue`
+    /// on each queue in turn, sharing `wait` between them.
+    async fn reserve(
+        &self,
+        queues: &[String],
+        _consumer: &str,
+        _visibility: Duration,
+        wait: Duration,
+    ) -> Result<Option<(String, String)>, BrokerError> {
+        let wait = wait / queues.len().max(1) as u32;
+        for queue in queues {
+            if let Ok(res) = tokio::time::timeout(wait, self.dequeue(queue)).await {
+                return res;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Push the visibility timeout of a value reserved by `consumer` back to
+    /// `visibility` from now, for values handled longer than their first timeout.
+    /// Does nothing if the value is no longer reserved by `consumer`.
+    async fn extend(
+        &self,
+        _queue: &str,
+        _consumer: &str,
+        _val: &str,
+        _visibility: Duration,
+    ) -> Result<(), BrokerError> {
+        Ok(())
+    }
+
+    /// Acknowledge a value returned by `reserve`, removing it for good.
+    async fn ack(&self, _queue: &str, _consumer: &str, _val: &str) -> Result<(), BrokerError> {
+        Ok(())
+    }
+
+    /// Give a value returned by `reserve` back to the head of `queue`,
+    /// for another consumer to take it right away.
+    ///
+    /// The default implementation is not atomic, the value may be seen twice.
+    async fn release(&self, queue: &str, consumer: &str, val: &str) -> Result<(), BrokerError> {
+        self.enqueue(queue, val).await?;
+        self.ack(queue, consumer, val).await
+    }
+
+    /// Move reserved values of every queue in `queues` whose visibility timeout
+    /// expired back to their queue.
+    /// Returns how many values were requeued.
+    async fn requeue_expired(&self, _queues: &[String]) -> Result<usize, BrokerError> {
+        Ok(0)
+    }
+
+    /// Wake up everyone waiting on `key` in `wait_notified`, now and later,
+    /// or until `ttl` elapsed if given.
+    async fn notify(&self, _key: &str, _ttl: Option<Duration>) -> Result<(), BrokerError> {
+        Ok(())
+    }
+
+    /// Wait until `notify` was called for `key`, at most for `timeout`.
+    ///
+    /// The default implementation cannot be notified and always sleeps for `timeout`,
+    /// turning waiters into pollers.
+    async fn wait_notified(&self, _key: &str, timeout: Duration) -> Result<(), BrokerError> {
+        tokio::time::sleep(timeout).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_from_url() {
+        assert!(builder_from_url("memory://".into()).is_ok());
+        assert!(builder_from_url("redis://127.0.0.1/".into()).is_ok());
+        assert!(matches!(
+            builder_from_url("amqp://localhost".into()),
+            Err(BrokerError::UnsupportedScheme(scheme)) if scheme == "amqp"
+        ));
+        assert!(matches!(
+            builder_from_url("localhost".into()),
+            Err(BrokerError::UnsupportedScheme(_))
+        ));
+        assert!(matches!(
+            builder_from_url("redis://localhost:notaport/".into()),
+            Err(BrokerError::RedisError(_))
+        ));
+    }
 }
@@ -1,59 +1,283 @@
 use super::{Broker, BrokerBuilder};
 use crate::error::BrokerError;
 use async_trait::async_trait;
+use futures::StreamExt;
 use redis::aio::ConnectionManager;
-use redis::Client;
+use redis::{Client, Script};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+
+// How often `reserve` checks an empty queue again while it waits.
+const RESERVE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How many notifications are buffered for a waiter that is slow to read them.
+const NOTIFY_CAPACITY: usize = 1024;
+
+// Move the head of the first queue that has a value to the processing list
+// of a consumer and record its deadline, all at once so that no value goes unrecorded.
+// KEYS: for each queue, in order: queue, processing, unacked, unacked owner, processing lists;
+// ARGV: deadline in millis.
+const RESERVE: &str = r#"
+for i = 1, #KEYS, 5 do
+    local val = redis.call('LMOVE', KEYS[i], KEYS[i + 1], 'LEFT', 'RIGHT')
+    if val then
+        redis.call('ZADD', KEYS[i + 2], ARGV[1], val)
+        redis.call('HSET', KEYS[i + 3], val, KEYS[i + 1])
+        redis.call('SADD', KEYS[i + 4], KEYS[i + 1])
+        return {KEYS[i], val}
+    end
+end
+return false
+"#;
+
+// Forget a value reserved by a consumer. The records of the value are left alone
+// if it was requeued and reserved by another consumer since.
+// KEYS: processing, unacked, unacked owner; ARGV: value.
+const ACK: &str = r#"
+redis.call('LREM', KEYS[1], 1, ARGV[1])
+if redis.call('HGET', KEYS[3], ARGV[1]) == KEYS[1] then
+    redis.call('ZREM', KEYS[2], ARGV[1])
+    redis.call('HDEL', KEYS[3], ARGV[1])
+end
+"#;
+
+// Give a value reserved by a consumer back to the head of the queue,
+// unless it was requeued since.
+// KEYS: queue, processing, unacked, unacked owner; ARGV: value.
+const RELEASE: &str = r#"
+if redis.call('LREM', KEYS[2], 1, ARGV[1]) == 0 then
+    return
+end
+if redis.call('HGET', KEYS[4], ARGV[1]) == KEYS[2] then
+    redis.call('ZREM', KEYS[3], ARGV[1])
+    redis.call('HDEL', KEYS[4], ARGV[1])
+end
+redis.call('LPUSH', KEYS[1], ARGV[1])
+"#;
+
+// Push back the deadline of a value, if the consumer still holds it.
+// KEYS: processing, unacked, unacked owner; ARGV: value, deadline in millis.
+const EXTEND: &str = r#"
+if redis.call('HGET', KEYS[3], ARGV[1]) == KEYS[1] then
+    redis.call('ZADD', KEYS[2], 'XX', ARGV[2], ARGV[1])
+end
+"#;
+
+// Requeue every reserved value whose deadline is due,
+// and every value of a processing list that was never recorded.
+// KEYS: for each queue: queue, unacked, unacked owner, processing lists; ARGV: now in millis.
+const REQUEUE_EXPIRED: &str = r#"
+local count = 0
+for i = 1, #KEYS, 4 do
+    local queue, unacked, owners, lists = KEYS[i], KEYS[i + 1], KEYS[i + 2], KEYS[i + 3]
+    for _, val in ipairs(redis.call('ZRANGEBYSCORE', unacked, '-inf', ARGV[1])) do
+        local owner = redis.call('HGET', owners, val)
+        if owner and redis.call('LREM', owner, 1, val) > 0 then
+            redis.call('LPUSH', queue, val)
+            count = count + 1
+        end
+        redis.call('ZREM', unacked, val)
+        redis.call('HDEL', owners, val)
+    end
+    for _, processing in ipairs(redis.call('SMEMBERS', lists)) do
+        for _, val in ipairs(redis.call('LRANGE', processing, 0, -1)) do
+            if not redis.call('HGET', owners, val) then
+                redis.call('LREM', processing, 1, val)
+                redis.call('LPUSH', queue, val)
+                count = count + 1
+            end
+        end
+        if redis.call('EXISTS', processing) == 0 then
+            redis.call('SREM', lists, processing)
+        end
+    end
+end
+return count
+"#;
+
+// Move every delayed value whose time came onto its queue.
+// KEYS: for each queue: queue, delayed; ARGV: now in millis.
+const PROMOTE_DUE: &str = r#"
+local count = 0
+for i = 1, #KEYS, 2 do
+    local due = redis.call('ZRANGEBYSCORE', KEYS[i + 1], '-inf', ARGV[1], 'LIMIT', 0, 1000)
+    for _, val in ipairs(due) do
+        redis.call('RPUSH', KEYS[i], val)
+        redis.call('ZREM', KEYS[i + 1], val)
+    end
+    count = count + #due
+end
+return count
+"#;
+
+fn delayed_key(queue: &str) -> String {
+    format!("{queue}:delayed")
+}
+
+fn processing_key(queue: &str, consumer: &str) -> String {
+    format!("{queue}:processing:{consumer}")
+}
+
+/// The set of the processing lists of `queue`, for the reaper to walk.
+fn processing_lists_key(queue: &str) -> String {
+    format!("{queue}:processing")
+}
+
+fn unacked_key(queue: &str) -> String {
+    format!("{queue}:unacked")
+}
+
+fn unacked_owner_key(queue: &str) -> String {
+    format!("{queue}:unacked:owner")
+}
+
+fn notify_key(key: &str) -> String {
+    format!("{key}:done")
+}
+
+fn now_millis() -> u128 {
+    millis(SystemTime::now())
+}
+
+fn millis(at: SystemTime) -> u128 {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// Wakes up `wait_notified` callers through a single pub/sub connection,
+/// shared by every broker built from the same builder.
+struct Notifier {
+    client: Client,
+    // the channels notified, and the task reading them off the connection
+    listener: Mutex<Option<(broadcast::Sender<String>, JoinHandle<()>)>>,
+}
+
+impl Notifier {
+    fn new(client: Client) -> Self {
+        Notifier {
+            client,
+            listener: Mutex::new(None),
+        }
+    }
+
+    /// Receive the channels notified from now on,
+    /// (re)connecting the listener if it is not running.
+    async fn subscribe(&self) -> Result<broadcast::Receiver<String>, BrokerError> {
+        let mut listener = self.listener.lock().await;
+        if let Some((tx, handle)) = listener.as_ref() {
+            if !handle.is_finished() {
+                return Ok(tx.subscribe());
+            }
+        }
+        let mut pubsub = self.client.get_async_connection().await?.into_pubsub();
+        pubsub.psubscribe(notify_key("*")).await?;
+        let (tx, rx) = broadcast::channel(NOTIFY_CAPACITY);
+        let sender = tx.clone();
+        let handle = tokio::spawn(async move {
+            let mut messages = pubsub.into_on_message();
+            while let Some(msg) = messages.next().await {
+                // nobody may be waiting, which is fine.
+                let _ = sender.send(msg.get_channel_name().to_string());
+            }
+        });
+        *listener = Some((tx, handle));
+        Ok(rx)
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        if let Some((_, handle)) = self.listener.get_mut().take() {
+            handle.abort();
+        }
+    }
+}
 
 pub struct RedisBrokerBuilder {
     _url: String,
     client: Client,
+    notifier: Arc<Notifier>,
 }
 
 #[async_trait]
 impl BrokerBuilder for RedisBrokerBuilder {
-    fn new(broker_url: String) -> Self
+    fn new(broker_url: String) -> Result<Self, BrokerError>
     where
         Self: Sized,
     {
-        let client = Client::open(broker_url.clone())
-            .map_err(|e| e.to_string())
-            .unwrap();
-        return RedisBrokerBuilder {
-            _url: broker_url.into(),
-            client: client,
-        };
+        let client = Client::open(broker_url.clone())?;
+        Ok(RedisBrokerBuilder {
+            _url: broker_url,
+            notifier: Arc::new(Notifier::new(client.clone())),
+            client,
+        })
     }
 
     async fn build(&self, _timeout: u32) -> Result<Box<dyn Broker>, BrokerError> {
-        let manager = self.client.get_tokio_connection_manager().await?;
-        return Ok(Box::new(RedisBroker { manager }));
+        let manager = self.client.get_connection_manager().await?;
+        return Ok(Box::new(RedisBroker {
+            manager,
+            notifier: self.notifier.clone(),
+        }));
     }
 }
 
 #[derive(Clone)]
 pub struct RedisBroker {
     manager: ConnectionManager,
+    notifier: Arc<Notifier>,
 }
 
 #[async_trait]
 impl Broker for RedisBroker {
-    async fn get(&self, key: &String) -> Result<Option<String>, BrokerError> {
+    async fn get(&self, key: &str) -> Result<Option<String>, BrokerError> {
         let mut conn = self.manager.clone();
         let res = redis::cmd("GET").arg(key).query_async(&mut conn).await?;
         return Ok(res);
     }
 
-    async fn set(&self, key: &String, val: &String) -> Result<(), BrokerError> {
+    async fn set(&self, key: &str, val: &str, ttl: Option<Duration>) -> Result<(), BrokerError> {
+        let mut conn = self.manager.clone();
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(key).arg(val);
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis() as u64);
+        }
+        cmd.query_async(&mut conn).await.map_err(|e| e.into())
+    }
+
+    async fn set_nx(
+        &self,
+        key: &str,
+        val: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, BrokerError> {
+        let mut conn = self.manager.clone();
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(key).arg(val).arg("NX");
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis() as u64);
+        }
+        // a nil reply means the key was already set
+        let res: Option<String> = cmd.query_async(&mut conn).await?;
+        Ok(res.is_some())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BrokerError> {
         let mut conn = self.manager.clone();
-        redis::cmd("SET")
+        redis::cmd("DEL")
             .arg(key)
-            .arg(val)
+            .arg(notify_key(key))
             .query_async(&mut conn)
             .await
             .map_err(|e| e.into())
     }
 
-    async fn enqueue(&self, queue: &String, val: &String) -> Result<(), BrokerError> {
+    async fn enqueue(&self, queue: &str, val: &str) -> Result<(), BrokerError> {
         let mut conn = self.manager.clone();
         redis::cmd("RPUSH")
             .arg(queue)
@@ -63,7 +287,32 @@ impl Broker for RedisBroker {
             .map_err(|e| e.into())
     }
 
-    async fn dequeue(&self, queue: &String) -> Result<Option<(String, String)>, BrokerError> {
+    async fn enqueue_at(&self, queue: &str, val: &str, at: SystemTime) -> Result<(), BrokerError> {
+        let mut conn = self.manager.clone();
+        redis::cmd("ZADD")
+            .arg(delayed_key(queue))
+            .arg(millis(at) as u64)
+            .arg(val)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn promote_due(&self, queues: &[String]) -> Result<usize, BrokerError> {
+        let mut conn = self.manager.clone();
+        let script = Script::new(PROMOTE_DUE);
+        let mut invocation = script.prepare_invoke();
+        for queue in queues {
+            invocation.key(queue).key(delayed_key(queue));
+        }
+        invocation
+            .arg(now_millis() as u64)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn dequeue(&self, queue: &str) -> Result<Option<(String, String)>, BrokerError> {
         // we should create a new connection for blocking command.
         // https://github.com/redis-rs/redis-rs/issues/453
         let mut conn = self.manager.clone();
@@ -74,4 +323,399 @@ impl Broker for RedisBroker {
             .await
             .map_err(|e| e.into())
     }
+
+    async fn peek(
+        &self,
+        queue: &str,
+        start: usize,
+        count: usize,
+    ) -> Result<Vec<String>, BrokerError> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.manager.clone();
+        redis::cmd("LRANGE")
+            .arg(queue)
+            .arg(start)
+            .arg(start + count - 1)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn remove(&self, queue: &str, val: &str) -> Result<usize, BrokerError> {
+        let mut conn = self.manager.clone();
+        redis::cmd("LREM")
+            .arg(queue)
+            .arg(0)
+            .arg(val)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn purge(&self, queue: &str) -> Result<usize, BrokerError> {
+        let mut conn = self.manager.clone();
+        let (len,): (usize,) = redis::pipe()
+            .atomic()
+            .cmd("LLEN")
+            .arg(queue)
+            .cmd("DEL")
+            .arg(queue)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(len)
+    }
+
+    async fn reserve(
+        &self,
+        queues: &[String],
+        consumer: &str,
+        visibility: Duration,
+        wait: Duration,
+    ) -> Result<Option<(String, String)>, BrokerError> {
+        let mut conn = self.manager.clone();
+        let script = Script::new(RESERVE);
+        // a script cannot block, so poll it until `wait` elapsed.
+        let until = Instant::now() + wait;
+        loop {
+            let deadline = now_millis() + visibility.as_millis();
+            let mut invocation = script.prepare_invoke();
+            for queue in queues {
+                invocation
+                    .key(queue)
+                    .key(processing_key(queue, consumer))
+                    .key(unacked_key(queue))
+                    .key(unacked_owner_key(queue))
+                    .key(processing_lists_key(queue));
+            }
+            let res: Option<(String, String)> = invocation
+                .arg(deadline as u64)
+                .invoke_async(&mut conn)
+                .await?;
+            if res.is_some() {
+                return Ok(res);
+            }
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            sleep(left.min(RESERVE_POLL_INTERVAL)).await;
+        }
+    }
+
+    async fn extend(
+        &self,
+        queue: &str,
+        consumer: &str,
+        val: &str,
+        visibility: Duration,
+    ) -> Result<(), BrokerError> {
+        let mut conn = self.manager.clone();
+        let deadline = now_millis() + visibility.as_millis();
+        Script::new(EXTEND)
+            .key(processing_key(queue, consumer))
+            .key(unacked_key(queue))
+            .key(unacked_owner_key(queue))
+            .arg(val)
+            .arg(deadline as u64)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn ack(&self, queue: &str, consumer: &str, val: &str) -> Result<(), BrokerError> {
+        let mut conn = self.manager.clone();
+        Script::new(ACK)
+            .key(processing_key(queue, consumer))
+            .key(unacked_key(queue))
+            .key(unacked_owner_key(queue))
+            .arg(val)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn release(&self, queue: &str, consumer: &str, val: &str) -> Result<(), BrokerError> {
+        let mut conn = self.manager.clone();
+        Script::new(RELEASE)
+            .key(queue)
+            .key(processing_key(queue, consumer))
+            .key(unacked_key(queue))
+            .key(unacked_owner_key(queue))
+            .arg(val)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn requeue_expired(&self, queues: &[String]) -> Result<usize, BrokerError> {
+        let mut conn = self.manager.clone();
+        let script = Script::new(REQUEUE_EXPIRED);
+        let mut invocation = script.prepare_invoke();
+        for queue in queues {
+            invocation
+                .key(queue)
+                .key(unacked_key(queue))
+                .key(unacked_owner_key(queue))
+                .key(processing_lists_key(queue));
+        }
+        invocation
+            .arg(now_millis() as u64)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn notify(&self, key: &str, ttl: Option<Duration>) -> Result<(), BrokerError> {
+        let mut conn = self.manager.clone();
+        let key = notify_key(key);
+        let mut pipe = redis::pipe();
+        // the key is for late waiters, the message for the ones already waiting.
+        pipe.atomic().cmd("SET").arg(&key).arg(1);
+        if let Some(ttl) = ttl {
+            pipe.arg("PX").arg(ttl.as_millis() as u64);
+        }
+        pipe.ignore().cmd("PUBLISH").arg(&key).arg(1).ignore();
+        pipe.query_async(&mut conn).await.map_err(|e| e.into())
+    }
+
+    async fn wait_notified(&self, key: &str, timeout: Duration) -> Result<(), BrokerError> {
+        let key = notify_key(key);
+        // subscribe before checking the key, otherwise a notify in between would be missed.
+        let mut rx = self.notifier.subscribe().await?;
+        let mut conn = self.manager.clone();
+        let notified: bool = redis::cmd("EXISTS")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await?;
+        if notified {
+            return Ok(());
+        }
+        let wait = async {
+            loop {
+                match rx.recv().await {
+                    Ok(channel) if channel == key => return,
+                    Ok(_) => {}
+                    // missed notifications may include ours, let the caller check again.
+                    Err(_) => return,
+                }
+            }
+        };
+        let _ = tokio::time::timeout(timeout, wait).await;
+        Ok(())
+    }
+}
+
+// These tests need a redis server, they only run when `REDIS_URL` points to one.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+    use uuid::Uuid;
+
+    async fn broker() -> Option<Box<dyn Broker>> {
+        let url = std::env::var("REDIS_URL").ok()?;
+        let builder = RedisBrokerBuilder::new(url).unwrap();
+        Some(builder.build(10).await.unwrap())
+    }
+
+    /// A key of its own for every test run.
+    fn key(name: &str) -> String {
+        format!("test:{}:{}", Uuid::new_v4(), name)
+    }
+
+    #[tokio::test]
+    async fn test_set_nx() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let key = key("lock");
+        let ttl = Some(Duration::from_secs(10));
+        assert!(broker.set_nx(&key, "a", ttl).await.unwrap());
+        assert!(!broker.set_nx(&key, "b", ttl).await.unwrap());
+        assert_eq!(Some("a".to_string()), broker.get(&key).await.unwrap());
+        broker.delete(&key).await.unwrap();
+        assert!(broker.set_nx(&key, "b", ttl).await.unwrap());
+        broker.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let queue = key("q");
+        broker.enqueue(&queue, "1").await.unwrap();
+        broker.enqueue(&queue, "2").await.unwrap();
+        assert_eq!(vec!["1", "2"], broker.peek(&queue, 0, 10).await.unwrap());
+        assert_eq!(2, broker.purge(&queue).await.unwrap());
+        assert_eq!(0, broker.purge(&queue).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reserve_ack() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let (high, low) = (key("high"), key("low"));
+        broker.enqueue(&low, "1").await.unwrap();
+        broker.enqueue(&high, "2").await.unwrap();
+
+        let queues = [high.clone(), low.clone()];
+        let visibility = Duration::from_millis(50);
+        let reserve = |wait| broker.reserve(&queues, "c", visibility, wait);
+        assert_eq!(
+            Some((high.clone(), "2".into())),
+            reserve(Duration::ZERO).await.unwrap()
+        );
+        let wait = Duration::from_millis(300);
+        assert_eq!(
+            Some((low.clone(), "1".into())),
+            reserve(wait).await.unwrap()
+        );
+        let started = Instant::now();
+        assert_eq!(None, reserve(wait).await.unwrap());
+        assert!(started.elapsed() >= wait);
+
+        broker.ack(&high, "c", "2").await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(1, broker.requeue_expired(&queues).await.unwrap());
+        assert_eq!(0, broker.requeue_expired(&queues).await.unwrap());
+        assert_eq!(vec!["1"], broker.peek(&low, 0, 10).await.unwrap());
+        broker.purge(&low).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_consumer() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let queue = key("q");
+        broker.enqueue(&queue, "1").await.unwrap();
+
+        let queues = [queue.clone()];
+        let visibility = Duration::from_millis(50);
+        broker
+            .reserve(&queues, "a", visibility, Duration::ZERO)
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(1, broker.requeue_expired(&queues).await.unwrap());
+        let visibility = Duration::from_secs(10);
+        broker
+            .reserve(&queues, "b", visibility, Duration::ZERO)
+            .await
+            .unwrap();
+
+        // "a" gave up on the value, it cannot touch the reservation of "b"
+        broker.ack(&queue, "a", "1").await.unwrap();
+        broker.release(&queue, "a", "1").await.unwrap();
+        assert!(broker.peek(&queue, 0, 10).await.unwrap().is_empty());
+        assert_eq!(0, broker.requeue_expired(&queues).await.unwrap());
+
+        broker
+            .extend(&queue, "b", "1", Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(1, broker.requeue_expired(&queues).await.unwrap());
+        broker.purge(&queue).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_requeue_unrecorded() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        // a value moved by an older release, which crashed before recording it
+        let client = Client::open(std::env::var("REDIS_URL").unwrap()).unwrap();
+        let mut conn = client.get_async_connection().await.unwrap();
+        let queue = key("q");
+        let processing = processing_key(&queue, "c");
+        redis::pipe()
+            .cmd("RPUSH")
+            .arg(&processing)
+            .arg("1")
+            .cmd("SADD")
+            .arg(processing_lists_key(&queue))
+            .arg(&processing)
+            .query_async::<_, ()>(&mut conn)
+            .await
+            .unwrap();
+
+        let queues = [queue.clone()];
+        assert_eq!(1, broker.requeue_expired(&queues).await.unwrap());
+        assert_eq!(vec!["1"], broker.peek(&queue, 0, 10).await.unwrap());
+        let lists: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(processing_lists_key(&queue))
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        assert!(lists.is_empty());
+        broker.purge(&queue).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_promote_due() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let (a, b) = (key("a"), key("b"));
+        let now = SystemTime::now();
+        broker
+            .enqueue_at(&a, "later", now + Duration::from_secs(60))
+            .await
+            .unwrap();
+        broker
+            .enqueue_at(&a, "first", now - Duration::from_secs(1))
+            .await
+            .unwrap();
+        broker
+            .enqueue_at(&b, "second", now - Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        let queues = [a.clone(), b.clone()];
+        assert_eq!(2, broker.promote_due(&queues).await.unwrap());
+        assert_eq!(0, broker.promote_due(&queues).await.unwrap());
+        assert_eq!(vec!["first"], broker.peek(&a, 0, 10).await.unwrap());
+        assert_eq!(vec!["second"], broker.peek(&b, 0, 10).await.unwrap());
+        for queue in [&a, &b] {
+            broker.purge(queue).await.unwrap();
+        }
+        broker.delete(&delayed_key(&a)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let Some(waiter) = broker().await else {
+            return;
+        };
+        let notifier = broker().await.unwrap();
+        let key = key("task");
+
+        let waiting = key.clone();
+        let h = tokio::spawn(async move {
+            waiter
+                .wait_notified(&waiting, Duration::from_secs(10))
+                .await
+                .unwrap()
+        });
+        sleep(Duration::from_millis(100)).await;
+        notifier
+            .notify(&key, Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        timeout(Duration::from_secs(1), h).await.unwrap().unwrap();
+
+        // notifications stick for late waiters
+        let started = Instant::now();
+        notifier
+            .wait_notified(&key, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        notifier.delete(&key).await.unwrap();
+    }
 }
@@ -1,12 +1,94 @@
-use std::io;
+use std::{any, io};
 
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::time::error::Elapsed;
 
-#[derive(Error, Debug)]
+/// Why a task did not produce a value.
+///
+/// Failures of the task itself are stored by the worker and handed back to the client,
+/// errors local to the client (e.g. `DeserdeError`) are never stored.
+#[derive(Error, Debug, Serialize, Deserialize)]
 pub enum TaskError {
     #[error("deserialization error: {0}")]
+    #[serde(skip)]
     DeserdeError(#[from] serde_json::Error),
+
+    #[error("task panicked: {0}")]
+    Panicked(String),
+
+    #[error("task failed with {kind}: {message}")]
+    Failed {
+        /// type name of the error returned by the task
+        kind: String,
+        /// the error if it serializes to a string, its JSON otherwise
+        message: String,
+        /// the error itself, serialized
+        data: Option<serde_json::Value>,
+    },
+
+    #[error("task expired before it was started")]
+    Expired,
+
+    #[error("task was revoked")]
+    Revoked,
+
+    #[error("task exceeded its time limit of {0:?}")]
+    TimeLimitExceeded(std::time::Duration),
+
+    #[error("task returned a value that cannot be serialized: {0}")]
+    Unserializable(String),
+}
+
+impl TaskError {
+    /// Wrap the error returned by a fallible task.
+    pub fn failed<E: Serialize>(err: E) -> TaskError {
+        let kind = short_type_name(any::type_name::<E>());
+        let data = serde_json::to_value(&err).ok();
+        let message = match &data {
+            Some(serde_json::Value::String(message)) => message.clone(),
+            Some(data) => data.to_string(),
+            None => kind.clone(),
+        };
+        TaskError::Failed {
+            kind,
+            message,
+            data,
+        }
+    }
+
+    /// The error returned by a fallible task,
+    /// if this is a failure that can be deserialized into `E`.
+    pub fn data<E: DeserializeOwned>(&self) -> Option<E> {
+        match self {
+            TaskError::Failed {
+                data: Some(data), ..
+            } => serde_json::from_value(data.clone()).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// `name` without the paths of its types,
+/// e.g. `Vec<Error>` for `alloc::vec::Vec<my::Error>`.
+fn short_type_name(name: &str) -> String {
+    let mut short = String::new();
+    // where the path being read starts in `short`
+    let mut start = 0;
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            short.truncate(start);
+        } else {
+            short.push(c);
+            if !(c.is_alphanumeric() || c == '_') {
+                start = short.len();
+            }
+        }
+    }
+    short
 }
 
 #[derive(Error, Debug)]
@@ -16,6 +98,9 @@ pub enum QueueError {
 
     #[error("duplicate task {0}")]
     DuplicateTask(String),
+
+    #[error("no dead-letter queue is configured")]
+    DeadLetterDisabled,
 }
 
 #[derive(Error, Debug)]
@@ -28,6 +113,24 @@ pub enum WorkerError {
 
     #[error("serialization error: {0}")]
     ProtocolError(#[from] serde_json::Error),
+
+    #[error("message error: {0}")]
+    MsgError(#[from] MsgError),
+}
+
+impl WorkerError {
+    /// Whether the message can never be handled, whichever worker tries.
+    pub fn is_poison(&self) -> bool {
+        matches!(
+            self,
+            WorkerError::ProtocolError(_)
+                | WorkerError::MsgError(_)
+                | WorkerError::TracerError(TracerError::TaskNotFound(_))
+                | WorkerError::TracerError(TracerError::StateNotFound(_))
+                // the payload does not fit the task, a task result is never one.
+                | WorkerError::TracerError(TracerError::ProtocolError(_))
+        )
+    }
 }
 
 #[derive(Error, Debug)]
@@ -54,6 +157,24 @@ pub enum ClientError {
     Timeout(#[from] Elapsed),
 }
 
+#[derive(Error, Debug)]
+pub enum BeatError {
+    #[error("client error: {0}")]
+    ClientError(#[from] ClientError),
+
+    #[error("broker error: {0}")]
+    BrokerError(#[from] BrokerError),
+
+    #[error("io error: {0}")]
+    IOError(#[from] io::Error),
+
+    #[error("duplicate periodic entry {0}")]
+    DuplicateEntry(String),
+
+    #[error("invalid schedule {0}")]
+    InvalidSchedule(String),
+}
+
 #[derive(Error, Debug)]
 pub enum MsgError {
     #[error("serialization error: {0}")]
@@ -67,10 +188,61 @@ pub enum TracerError {
 
     #[error("cannot found task {0}")]
     TaskNotFound(String),
+
+    #[error("no state of type {0} is managed")]
+    StateNotFound(String),
+
+    #[error("task error: {0}")]
+    TaskError(#[from] TaskError),
 }
 
 #[derive(Error, Debug)]
 pub enum BrokerError {
     #[error("redis error: {0}")]
     RedisError(#[from] redis::RedisError),
+
+    #[error("unsupported broker scheme {0}")]
+    UnsupportedScheme(String),
+
+    #[error("operation not supported by the broker: {0}")]
+    Unsupported(String),
+
+    /// An error of a broker not shipped with this crate.
+    #[error("broker error: {0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    enum ParseError {
+        Empty,
+        Invalid { at: usize },
+    }
+
+    #[test]
+    fn test_failed_message() {
+        let failed = |err| match err {
+            TaskError::Failed { kind, message, .. } => (kind, message),
+            _ => unreachable!(),
+        };
+        let (kind, message) = failed(TaskError::failed("no user"));
+        assert_eq!(("&str", "no user"), (&kind[..], &message[..]));
+        let (kind, message) = failed(TaskError::failed(ParseError::Empty));
+        assert_eq!(("ParseError", "Empty"), (&kind[..], &message[..]));
+        let (_, message) = failed(TaskError::failed(ParseError::Invalid { at: 3 }));
+        assert_eq!(r#"{"Invalid":{"at":3}}"#, message);
+
+        // generic errors keep their arguments
+        let (kind, message) = failed(TaskError::failed(vec![ParseError::Empty]));
+        assert_eq!(
+            ("Vec<ParseError>", r#"["Empty"]"#),
+            (&kind[..], &message[..])
+        );
+        let map = std::collections::HashMap::from([("a".to_string(), "b".to_string())]);
+        let (kind, _) = failed(TaskError::failed(map));
+        assert_eq!("HashMap<String, String>", kind);
+    }
 }
@@ -1 +1,2 @@
+pub use async_trait::async_trait;
 pub use serde::{Deserialize, Serialize};
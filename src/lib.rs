@@ -1,6 +1,6 @@
 pub mod app;
 pub mod async_result;
-pub(crate) mod broker;
+pub mod broker;
 pub mod error;
 pub mod export;
 mod utils;
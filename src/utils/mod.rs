@@ -1,4 +1,4 @@
-#![allow(dead_code)]
+#![allow(dead_code, clippy::enum_variant_names)]
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;